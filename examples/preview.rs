@@ -0,0 +1,45 @@
+//! render the screen of every mode to png on the host, for checking layouts without a panel
+//!
+//! ```bash
+//! cp src/config.example src/config.rs
+//! cargo +stable run --no-default-features --features simulator \
+//!     --target x86_64-unknown-linux-gnu --example preview
+//! ```
+
+use co2_monitor::canvas::Screen;
+use co2_monitor::e_paper::EPD_2IN9_V2;
+use co2_monitor::net::NetStatus;
+use co2_monitor::scd41::MeasureResult;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::*;
+use embedded_graphics_simulator::{OutputSettingsBuilder, SimulatorDisplay};
+
+fn main() {
+    let size = EPD_2IN9_V2.size();
+    let mut screen = Screen::new_with_history(&size, 60);
+    screen.set_net_status(NetStatus::Connected);
+    // co2 rising through the levels for the sparkline and the trend arrow
+    for i in 0..60 {
+        let m = MeasureResult {
+            co2_ppm: 600 + i * 12,
+            temp: 23.4,
+            hum: 41.2,
+        };
+        screen.render(&m);
+    }
+
+    let settings = OutputSettingsBuilder::new().scale(2).build();
+    for _ in 0..3 {
+        let mode = screen.next_mode();
+        screen.redraw();
+        let canvas = screen.canvas();
+        let mut display = SimulatorDisplay::<Rgb888>::new(canvas.size());
+        canvas.draw_preview(&mut display).unwrap();
+        let path = format!("preview-{:?}.png", mode).to_lowercase();
+        display
+            .to_rgb_output_image(&settings)
+            .save_png(&path)
+            .unwrap();
+        println!("saved {}", path);
+    }
+}
@@ -1,13 +1,41 @@
+import time
 import struct
 import asyncio
 from aiohttp import web
-from prometheus_client import Gauge, generate_latest
+from prometheus_client import REGISTRY, generate_latest
+from prometheus_client.core import GaugeMetricFamily
 
 PORT = 7004
 
-temperature_gauge = Gauge('temperature', 'Current temperature in Celsius')
-humidity_gauge = Gauge('humidity', 'Current humidity percentage')
-co2_gauge = Gauge('co2_ppm', 'Current CO2 concentration in ppm')
+
+class MeasureCollector:
+    """latest measure result, exported with the time it was measured"""
+
+    def __init__(self):
+        self.latest = None
+
+    def update(self, temperature, humidity, co2, timestamp):
+        # queued results are sent oldest first after the live one, never go back in time
+        if self.latest is not None and timestamp < self.latest[3]:
+            return
+        self.latest = (temperature, humidity, co2, timestamp)
+
+    def collect(self):
+        if self.latest is None:
+            return
+        temperature, humidity, co2, timestamp = self.latest
+        for name, doc, value in (
+            ('temperature', 'Current temperature in Celsius', temperature),
+            ('humidity', 'Current humidity percentage', humidity),
+            ('co2_ppm', 'Current CO2 concentration in ppm', co2),
+        ):
+            gauge = GaugeMetricFamily(name, doc)
+            gauge.add_metric([], value, timestamp=timestamp)
+            yield gauge
+
+
+collector = MeasureCollector()
+REGISTRY.register(collector)
 
 async def handle_metrics(request):
     data = generate_latest()
@@ -24,13 +52,17 @@ async def start_udp_server():
             # print(f'received {len(data)} bytes from {addr}')
             try:
                 if len(data) == 10:
+                    # live result
                     temperature, humidity, co2 = struct.unpack('>ffH', data)
-                    print(f"{addr}: temperature {temperature:.2f} °C, humidity: {humidity:.2f} %, co2_ppm: {co2} ppm")
-                    temperature_gauge.set(temperature)
-                    humidity_gauge.set(humidity)
-                    co2_gauge.set(co2)
+                    age_ms = 0
+                elif len(data) == 14:
+                    # queued result, with the ms passed since it was measured
+                    temperature, humidity, co2, age_ms = struct.unpack('>ffHI', data)
                 else:
                     print(f"invalid data len: {len(data)}")
+                    return
+                print(f"{addr}: temperature {temperature:.2f} °C, humidity: {humidity:.2f} %, co2_ppm: {co2} ppm, age: {age_ms} ms")
+                collector.update(temperature, humidity, co2, time.time() - age_ms / 1000)
             except Exception as e:
                 print(f"unpack data: {e}")
 
@@ -0,0 +1,104 @@
+use embedded_hal::digital::OutputPin;
+use log::{info, warn};
+
+/// drive a buzzer or led when co2 is too high
+///
+/// hysteresis: the pin goes high when co2 is above `set_ppm` and goes low only
+/// after it drops below `clear_ppm`, so it does not chatter around one limit.
+/// `clear_ppm` should be lower than `set_ppm`, e.g. 1200 and 1000
+pub struct Co2Alert<P> {
+    output: P,
+    set_ppm: u16,
+    clear_ppm: u16,
+    active: bool,
+}
+
+impl<P: OutputPin> Co2Alert<P> {
+    pub fn new(mut output: P, set_ppm: u16, clear_ppm: u16) -> Self {
+        if let Err(err) = output.set_low() {
+            warn!("set alert pin low error: {:?}", err);
+        }
+        Co2Alert {
+            output,
+            set_ppm,
+            clear_ppm,
+            active: false,
+        }
+    }
+
+    /// update the pin by a new result, return whether the alert is on
+    pub fn update(&mut self, co2_ppm: u16) -> bool {
+        if !self.active && co2_ppm > self.set_ppm {
+            warn!("co2 {}ppm above {}ppm, alert on", co2_ppm, self.set_ppm);
+            self.active = true;
+            if let Err(err) = self.output.set_high() {
+                warn!("set alert pin high error: {:?}", err);
+            }
+        } else if self.active && co2_ppm < self.clear_ppm {
+            info!("co2 {}ppm below {}ppm, alert off", co2_ppm, self.clear_ppm);
+            self.active = false;
+            if let Err(err) = self.output.set_low() {
+                warn!("set alert pin low error: {:?}", err);
+            }
+        }
+        self.active
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    // level of the pin, shared with the test after the pin is moved into the alert
+    struct MockPin(Rc<Cell<bool>>);
+
+    impl embedded_hal::digital::ErrorType for MockPin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockPin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn hysteresis_transitions() {
+        let level = Rc::new(Cell::new(true));
+        let mut alert = Co2Alert::new(MockPin(level.clone()), 1200, 1000);
+        // the pin starts low
+        assert!(!level.get());
+
+        // (co2, alert on) in order
+        let steps = [
+            (1100, false),
+            (1200, false),
+            (1201, true),
+            // between the limits, stay on
+            (1150, true),
+            (1000, true),
+            (999, false),
+            // between the limits, stay off
+            (1100, false),
+            (1300, true),
+        ];
+        for (co2_ppm, on) in steps {
+            assert_eq!(alert.update(co2_ppm), on, "co2 {}", co2_ppm);
+            assert_eq!(alert.is_active(), on, "co2 {}", co2_ppm);
+            assert_eq!(level.get(), on, "co2 {}", co2_ppm);
+        }
+    }
+}
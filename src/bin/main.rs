@@ -7,20 +7,22 @@
 )]
 extern crate alloc;
 
+use alloc::format;
 use esp_hal::rng::Rng;
 use esp_hal::timer::timg::TimerGroup;
 use esp_hal::{
     clock::CpuClock,
     delay::Delay,
-    gpio::{Input, InputConfig, Level, Output, OutputConfig},
+    gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull},
     i2c::master::{Config as I2cConfig, I2c},
     main,
-    spi::master::{Config as SpiConfig, Spi},
+    rtc_cntl::{Rtc, RwdtStage},
+    spi::master::Spi,
     time,
 };
 use esp_wifi::wifi;
 
-use blocking_network_stack::{IoError, Stack, UdpSocket};
+use blocking_network_stack::Stack;
 use embedded_graphics::image::Image;
 use embedded_graphics::pixelcolor::Gray4;
 use embedded_graphics::prelude::*;
@@ -30,33 +32,96 @@ use tinybmp::Bmp;
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     warn!("{}", info);
+    logger::dump_log_buffer();
     loop {}
 }
 
-use esp_println::logger;
+use co2_monitor::logger;
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
+use co2_monitor::alert::Co2Alert;
+use co2_monitor::button::Button;
 use co2_monitor::canvas::{Canvas, Screen};
-use co2_monitor::e_paper::EPaper;
+use co2_monitor::e_paper::{self, EPaper, EspEPaper};
+use co2_monitor::error::Error;
+use co2_monitor::net::{MetricSink, NetStatus};
 use co2_monitor::scd41::{MeasureResult, SCD41};
-use co2_monitor::utils::debug_alloc;
-use co2_monitor::{config, net};
+use co2_monitor::selftest::SelfTest;
+use co2_monitor::utils::{debug_alloc, HEAP_WATERMARK};
+use co2_monitor::{config, device, net, nvs, power};
 use log::{debug, info, warn};
 
+// reset the chip if the main loop is not fed in time, one iteration may take
+// up to 60s waiting for the scd data plus the display refresh
+const WATCHDOG_TIMEOUT_MS: u64 = 120_000;
+// the watchdog is fed at least this often while waiting for the next measurement
+const WAIT_SLICE_MS: u32 = 10_000;
+// smaller changes are treated as noise and not sent or displayed
+const CO2_DELTA: u16 = 5;
+const TEMP_DELTA: f32 = 0.1;
+const HUM_DELTA: f32 = 0.5;
+// hold the button this long at boot for a factory reset
+const FACTORY_RESET_HOLD_MS: u32 = 5_000;
+// check again after this if the sensor has no new sample yet
+const NOT_READY_RETRY_MS: u32 = 2_000;
+// wait before restarting on a fatal error, so the panel can be read
+const RESTART_DELAY_MS: u32 = 30_000;
+
 #[main]
 fn main() -> ! {
     // logger::init_logger(log::LevelFilter::Trace);
+    // logger::init_logger_with(logger::LogConfig::new().module("co2_monitor::e_paper", log::LevelFilter::Debug));
     logger::init_logger(log::LevelFilter::Info);
     esp_alloc::heap_allocator!(size: 128 * 1024);
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
+    let mut selftest = SelfTest::new();
+    selftest.record("heap", esp_alloc::HEAP.free() > 0);
+
+    // NOTE: call `cfg.save()` once to store a config in flash
+    let cfg = nvs::Config::load();
+
+    // the panel is set up first to show wifi errors
+    let power = Output::new(peripherals.GPIO15, Level::Low, OutputConfig::default());
+    let busy = Input::new(peripherals.GPIO2, InputConfig::default());
+    let reset = Output::new(peripherals.GPIO4, Level::Low, OutputConfig::default());
+    let dc = Output::new(peripherals.GPIO16, Level::Low, OutputConfig::default());
+    let (cs, clk, din) = (peripherals.GPIO17, peripherals.GPIO5, peripherals.GPIO18);
+    let spi = Spi::new(
+        peripherals.SPI2,
+        e_paper::spi_config(e_paper::DEFAULT_SPI_FREQUENCY_MHZ),
+    )
+    .unwrap()
+    .with_cs(cs)
+    .with_sck(clk)
+    .with_mosi(din);
+
+    let panel = e_paper::EPD_2IN9_V2;
+    let size = panel.size();
+    let ep = EPaper::new(&panel, spi, power, busy, reset, dc, Delay::new());
+    ep.set_full_refresh_every(cfg.full_refresh_every);
+    let metric_server = match cfg.metric_endpoint() {
+        Ok(endpoint) => endpoint,
+        Err(err) => restart_with_message(&ep, "BAD CONFIG", &err),
+    };
+    selftest.check("panel", ep.init_black_white());
+    // the panel is idle at boot, a good time to clear the ghosting
+    if let Err(err) = ep.deep_clean(config::DEEP_CLEAN_CYCLES) {
+        warn!("deep clean e-paper error: {:?}", err);
+    }
 
     let time_group = TimerGroup::new(peripherals.TIMG0);
     let mut rng = Rng::new(peripherals.RNG);
     let rand = rng.random();
-    let wifi_controller = esp_wifi::init(time_group.timer0, rng).unwrap();
-    let (mut controller, interfaces) = wifi::new(&wifi_controller, peripherals.WIFI).unwrap();
+    let wifi_controller = match esp_wifi::init(time_group.timer0, rng) {
+        Ok(wifi_controller) => wifi_controller,
+        Err(err) => restart_with_message(&ep, "WIFI INIT FAILED", &err.into()),
+    };
+    let (mut controller, interfaces) = match wifi::new(&wifi_controller, peripherals.WIFI) {
+        Ok(wifi) => wifi,
+        Err(err) => restart_with_message(&ep, "WIFI INIT FAILED", &err.into()),
+    };
     let device = interfaces.sta;
 
     let mut socket_set_entries: [iface::SocketStorage; 3] = Default::default();
@@ -68,16 +133,13 @@ fn main() -> ! {
         data: b"esp-wifi",
     }]);
     ss.add(dhcp_socket);
-    let stack = create_network_stack(&mut controller, device, ss, rand);
-
-    let mut sb = net::SocketBuff::new();
-    let mut socket = stack.get_udp_socket(
-        sb.rx_meta.as_mut_slice(),
-        &mut sb.rx_buffer,
-        sb.tx_meta.as_mut_slice(),
-        &mut sb.tx_buffer,
+    // a failure is only recorded here, the restart waits until the checklist is shown
+    let stack = create_network_stack(&mut controller, device, ss, rand, &cfg);
+    selftest.record("wifi", controller.is_connected().unwrap_or(false));
+    selftest.record(
+        "dhcp",
+        stack.as_ref().is_ok_and(|stack| stack.is_iface_up()),
     );
-    socket.bind(config::METRIC_PORT).unwrap();
 
     // led test
     // let mut led = Output::new(peripherals.GPIO2, Level::High, OutputConfig::default());
@@ -85,34 +147,73 @@ fn main() -> ! {
     let delay = Delay::new();
     // delay.delay_millis(1000);
 
-    let power = Output::new(peripherals.GPIO15, Level::Low, OutputConfig::default());
-    let busy = Input::new(peripherals.GPIO2, InputConfig::default());
-    let reset = Output::new(peripherals.GPIO4, Level::Low, OutputConfig::default());
-    let dc = Output::new(peripherals.GPIO16, Level::Low, OutputConfig::default());
-    let (cs, clk, din) = (peripherals.GPIO17, peripherals.GPIO5, peripherals.GPIO18);
-    let spi = Spi::new(peripherals.SPI2, SpiConfig::default())
-        .unwrap()
-        .with_cs(cs)
-        .with_sck(clk)
-        .with_mosi(din);
-
-    let size = Size::new(296, 128);
-    let ep = EPaper::new(&size, spi, power, busy, reset, dc);
-    ep.init_black_white().unwrap();
-    info!("init finish");
     delay.delay_millis(1000);
 
-    // power up scd sensor
-    let mut scd_power = Output::new(peripherals.GPIO23, Level::High, OutputConfig::default());
-    scd_power.set_high();
+    let scd_power = Output::new(peripherals.GPIO23, Level::Low, OutputConfig::default());
     let i2c = I2c::new(peripherals.I2C0, I2cConfig::default())
         .unwrap()
         .with_scl(peripherals.GPIO22)
         .with_sda(peripherals.GPIO21);
-    let mut scd = SCD41::new(i2c);
+    let mut scd = SCD41::new_with_power(i2c, scd_power);
+    // power up scd sensor
+    if let Err(err) = scd.power_on() {
+        warn!("power on scd error: {:?}", err);
+    }
     info!("scd init");
+    // found devices are logged in debug level
+    if log::log_enabled!(log::Level::Debug) && scd.scan_bus().is_empty() {
+        warn!("no i2c device found, check wiring and power");
+    }
+    if let Some(serial) = selftest.check("scd41", scd.get_serial_number()) {
+        info!("scd serial number: 0x{:012x}", serial);
+    }
+    // shown until the first measurement
+    let shown = selftest
+        .render(&size, ep.rotation())
+        .and_then(|data| ep.display(data.as_slice(), true))
+        .and_then(|_| ep.halt());
+    if let Err(err) = shown {
+        warn!("display self test error: {:?}", err);
+    }
+    let stack = match stack {
+        Ok(stack) => stack,
+        // keep the checklist on the panel instead of the error message
+        Err(err) => restart("WIFI FAILED", &err),
+    };
+
+    let mut sb = net::SocketBuff::new();
+    let mut socket = stack.get_udp_socket(
+        sb.rx_meta.as_mut_slice(),
+        &mut sb.rx_buffer,
+        sb.tx_meta.as_mut_slice(),
+        &mut sb.tx_buffer,
+    );
+    socket.bind(metric_server.port).unwrap();
+    let ntp_server = net::parse_ip(config::NTP_SERVER);
+    let clock = match ntp_server.and_then(|ip| net::ntp::sync(&mut socket, ip)) {
+        Ok(clock) => Some(clock),
+        Err(err) => {
+            warn!("ntp sync error: {:?}", err);
+            None
+        }
+    };
+    // NOTE: use `net::InfluxEncoder { sensor: "esp", clock }` for influxdb
+    // NOTE: use `net::JsonEncoder` for json consumers
+    let encoder = net::BinaryEncoder;
+    // NOTE: use `net::mqtt::MqttSink` with a tcp socket to publish to a mqtt broker
+    let mut sink = net::UdpSink::new(socket, metric_server, &encoder);
+    let client_config = client_config(&cfg);
+    if config::WIFI_OFF_BETWEEN_SENDS {
+        info!("wifi off until the next send");
+        radio_off(&mut controller);
+    }
+    // keep metrics while wifi is down
+    let mut metric_queue = net::MetricQueue::new(64);
+
     // NOTE: adjust temperature offset, default is 4.0
     // scd_setting(&scd, 1.5);
+    // NOTE: or calibrate against a reference thermometer before start
+    // scd.calibrate_temperature_to(23.0).unwrap();
 
     if let Err(err) = scd.start_low_power() {
         panic!("error: {:?}", err);
@@ -121,53 +222,265 @@ fn main() -> ! {
     info!("scd start");
     // delay.delay_millis(1000);
 
-    let mut screen = Screen::new(&size);
+    // numbers are smoothed to stop the last digit flickering, metrics are sent raw
+    let mut screen = Screen::new_with_history(&size, 60).with_smoothing(0.3);
+    // boot button on most boards, press to switch screen mode
+    let mut button = Button::new(Input::new(
+        peripherals.GPIO0,
+        InputConfig::default().with_pull(Pull::Up),
+    ));
+    // hold the button at boot to reset everything
+    if button.is_held(&delay, FACTORY_RESET_HOLD_MS) {
+        warn!("button held at boot, factory reset");
+        match device::factory_reset(&mut scd, &ep) {
+            Ok(_) => {
+                info!("factory reset finish, restart");
+            }
+            Err(err) => {
+                warn!("factory reset error: {:?}, restart", err);
+            }
+        }
+        esp_hal::system::software_reset();
+    }
+    // battery sense on GPIO35 through two equal resistors
+    let mut battery = if config::LOW_BATTERY_MV != 0 {
+        Some(power::Battery::new(peripherals.ADC1, peripherals.GPIO35, 2))
+    } else {
+        None
+    };
+    // buzzer or led for high co2
+    let mut co2_alert = if config::CO2_ALERT_PPM != 0 {
+        let output = Output::new(peripherals.GPIO25, Level::Low, OutputConfig::default());
+        Some(Co2Alert::new(
+            output,
+            config::CO2_ALERT_PPM,
+            config::CO2_ALERT_CLEAR_PPM,
+        ))
+    } else {
+        None
+    };
+    // the panel is powered off while the supply is low
+    let mut panel_parked = false;
+    // results are not updated faster than the sensor produces them
+    let measure_interval_ms = if cfg.measure_interval_ms < scd.sample_interval_ms() {
+        warn!(
+            "measure interval {}ms is shorter than the sensor interval, use {}ms",
+            cfg.measure_interval_ms,
+            scd.sample_interval_ms()
+        );
+        scd.sample_interval_ms()
+    } else {
+        cfg.measure_interval_ms
+    };
     let mut count = 1;
-    let full_screen_update_count = 100;
-    let mut last_measure = Default::default();
+    let mut last_deep_clean = time::Instant::now();
+    let mut last_measure = MeasureResult::default();
+    let mut rtc = Rtc::new(peripherals.LPWR);
+    rtc.rwdt.set_timeout(
+        RwdtStage::Stage0,
+        time::Duration::from_millis(WATCHDOG_TIMEOUT_MS),
+    );
+    rtc.rwdt.enable();
+    info!("watchdog enabled, timeout: {}ms", WATCHDOG_TIMEOUT_MS);
     loop {
+        rtc.rwdt.feed();
+        let started_at = time::Instant::now();
+        if let Err(err) = sink.poll() {
+            warn!("poll metric sink error: {:?}", err);
+        }
+        // never start a refresh the supply can not finish
+        let supply_ok = !battery
+            .as_mut()
+            .is_some_and(|battery| battery.is_below(config::LOW_BATTERY_MV));
         info!("scd measure");
+        let mut interval_ms = measure_interval_ms;
         // match scd.measure_oneshot() {
-        match scd.measure() {
-            Ok(m) => {
-                if last_measure == m {
-                    info!("not change");
-                    delay.delay_millis(10000);
-                    continue;
-                }
+        // NOTE: `scd.poll()` is for loops spinning on `stack.work()`, this one sleeps in
+        // `button.wait_press` between checks, so one `measure_if_ready` per wake up is enough.
+        // the sensor runs in low power periodic mode, so `power_down` and `wake_up` of the
+        // single shot mode are not used either
+        match scd.measure_if_ready() {
+            Ok(None) => {
+                debug!("scd data not ready, retry in {}ms", NOT_READY_RETRY_MS);
+                interval_ms = NOT_READY_RETRY_MS;
+            }
+            Ok(Some(m)) => {
                 info!("co2: {}, temp: {}, hum: {}", m.co2_ppm, m.temp, m.hum);
-                match send_metric(&mut socket, &m) {
-                    Ok(_) => {}
+                if let Some(co2_alert) = &mut co2_alert {
+                    co2_alert.update(m.co2_ppm);
+                }
+                if let Some(clock) = &clock {
+                    debug!("measured at {}ms", clock.now_ms());
+                }
+                let link_up = !config::WIFI_OFF_BETWEEN_SENDS
+                    || match radio_on(&mut controller, &stack, &client_config) {
+                        Ok(_) => true,
+                        Err(err) => {
+                            warn!("reconnect wifi error: {:?}", err);
+                            false
+                        }
+                    };
+                let mut net_status = match controller.is_connected() {
+                    Ok(true) if stack.is_iface_up() => NetStatus::Connected,
+                    Ok(true) => NetStatus::Connecting,
+                    Ok(false) => NetStatus::Disconnected,
                     Err(err) => {
-                        warn!("failed to send metric: {:?}", err);
+                        warn!("get wifi status error: {:?}", err);
+                        NetStatus::Disconnected
+                    }
+                };
+                // queued results are sent first, so the server gets them in order
+                metric_queue.push(m);
+                // udp send only fills the tx buffer, so don't drain into a dead link
+                match link_up.then(|| metric_queue.drain_to(&mut sink)) {
+                    None => {
+                        warn!("wifi down, {} queued", metric_queue.len());
+                    }
+                    Some(Ok(sent)) => {
+                        debug!("sent {} metrics", sent);
+                    }
+                    Some(Err(err)) => {
+                        warn!(
+                            "failed to send metric, {} queued: {:?}",
+                            metric_queue.len(),
+                            err
+                        );
+                        if net_status == NetStatus::Connected {
+                            net_status = NetStatus::SendFailed;
+                        }
                     }
                 }
-                // NOTE: show memory alloc before and after render canvas
-                debug_alloc("before render");
-                let data = screen.render(&m);
-                debug_alloc("render");
-                debug!("data len: {}", data.len());
-                if count % full_screen_update_count == 0 {
-                    ep.init_black_white().unwrap();
-                    ep.display_black_white(data.as_slice()).unwrap();
-                    debug_alloc("after display");
+                if config::WIFI_OFF_BETWEEN_SENDS && link_up {
+                    // flush the tx buffer before the radio goes down
+                    sink.socket().work();
+                    radio_off(&mut controller);
+                }
+                screen.set_net_status(net_status);
+                // the alert and the metric above get every reading, the delta only saves a refresh
+                if !m.differs_significantly(&last_measure, CO2_DELTA, TEMP_DELTA, HUM_DELTA) {
+                    info!("not change, skip render");
                 } else {
-                    ep.display_partial(data.as_slice()).unwrap();
-                    debug_alloc("after display partial");
+                    // NOTE: show memory alloc before and after render canvas
+                    debug_alloc("before render");
+                    let rendered = screen.render(&m);
+                    debug_alloc("render");
+                    match rendered {
+                        Some(data) => {
+                            debug!("data len: {}", data.len());
+                            match guarded_update_display(
+                                &ep,
+                                data.as_slice(),
+                                supply_ok,
+                                &mut panel_parked,
+                            ) {
+                                Ok(_) => {
+                                    info!("display finish");
+                                }
+                                Err(err) => {
+                                    warn!("failed to update display: {:?}", err);
+                                    screen.invalidate();
+                                }
+                            }
+                        }
+                        None => {
+                            info!("screen not changed");
+                        }
+                    }
+                    last_measure = m;
                 }
-                info!("display finish");
-                ep.halt().unwrap();
-                last_measure = m;
+            }
+            Err(Error::NotStarted) => {
+                warn!("scd measurement not started, skip update");
             }
             Err(err) => {
                 warn!("error: {:?}", err);
             }
         }
 
-        info!("updated, count: {}", count);
+        // after the send, so it does not delay a metric
+        if supply_ok && deep_clean_due(last_deep_clean, config::DEEP_CLEAN_INTERVAL_HOURS) {
+            info!("deep clean e-paper");
+            rtc.rwdt.feed();
+            last_deep_clean = time::Instant::now();
+            let result = ep.deep_clean(config::DEEP_CLEAN_CYCLES).and_then(|_| {
+                let data = screen.redraw();
+                guarded_update_display(&ep, data.as_slice(), supply_ok, &mut panel_parked)
+            });
+            if let Err(err) = result {
+                warn!("deep clean e-paper error: {:?}", err);
+                screen.invalidate();
+            }
+        }
+
+        info!(
+            "updated, count: {}, peak heap used: {}",
+            count,
+            HEAP_WATERMARK.poll()
+        );
         count += 1;
-        delay.delay_millis(10000);
+        // poll the button while waiting for the next measurement
+        let elapsed = started_at.elapsed().as_millis() as u32;
+        let mut remaining = interval_ms.saturating_sub(elapsed);
+        while remaining > 0 {
+            rtc.rwdt.feed();
+            let slice = remaining.min(WAIT_SLICE_MS);
+            let Some(waited) = button.wait_press(&delay, slice) else {
+                remaining -= slice;
+                continue;
+            };
+            remaining -= waited;
+            info!("switch screen mode to {:?}", screen.next_mode());
+            let data = screen.redraw();
+            if let Err(err) =
+                guarded_update_display(&ep, data.as_slice(), supply_ok, &mut panel_parked)
+            {
+                warn!("failed to update display: {:?}", err);
+                screen.invalidate();
+            }
+        }
+    }
+}
+
+fn update_display(ep: &EspEPaper, data: &[u8]) -> Result<(), Error> {
+    // full refresh is done by the driver after enough partial updates
+    let started_at = time::Instant::now();
+    ep.display(data, false)?;
+    // for comparing spi settings on the panel, the busy wait is included
+    debug!("display took {}ms", started_at.elapsed().as_millis());
+    debug_alloc("after display");
+    ep.halt()
+}
+
+/// whether `interval_hours` passed since the `last` deep clean, 0 hours is never
+fn deep_clean_due(last: time::Instant, interval_hours: u32) -> bool {
+    interval_hours != 0
+        && last.elapsed() >= time::Duration::from_secs(interval_hours as u64 * 60 * 60)
+}
+
+/// update display only if `supply_ok`, otherwise power off the panel once so it is
+/// left in a clean state. the first update after the supply recovers is a full refresh
+fn guarded_update_display(
+    ep: &EspEPaper,
+    data: &[u8],
+    supply_ok: bool,
+    parked: &mut bool,
+) -> Result<(), Error> {
+    if !supply_ok {
+        if !*parked {
+            warn!("park e-paper until the supply recovers");
+            *parked = true;
+            ep.power_off()?;
+        }
+        return Ok(());
+    }
+    if *parked {
+        info!("supply recovered, wake up e-paper");
+        *parked = false;
+        // full refresh inits the panel again
+        ep.display(data, true)?;
+        return ep.halt();
     }
+    update_display(ep, data)
 }
 
 #[allow(dead_code)]
@@ -184,17 +497,18 @@ fn _backup_for_img_display() -> ! {
     let reset = Output::new(peripherals.GPIO4, Level::Low, OutputConfig::default());
     let dc = Output::new(peripherals.GPIO16, Level::Low, OutputConfig::default());
     let (cs, clk, din) = (peripherals.GPIO17, peripherals.GPIO5, peripherals.GPIO18);
-    let spi = Spi::new(peripherals.SPI2, SpiConfig::default())
-        .unwrap()
-        .with_cs(cs)
-        .with_sck(clk)
-        .with_mosi(din);
-
-    let size = Size::new(296, 128);
-    let ep = EPaper::new(&size, spi, power, busy, reset, dc);
-    ep.init_gray4().unwrap();
-    info!("init finish");
-    delay.delay_millis(1000);
+    let spi = Spi::new(
+        peripherals.SPI2,
+        e_paper::spi_config(e_paper::DEFAULT_SPI_FREQUENCY_MHZ),
+    )
+    .unwrap()
+    .with_cs(cs)
+    .with_sck(clk)
+    .with_mosi(din);
+
+    let panel = e_paper::EPD_2IN9_V2;
+    let size = panel.size();
+    let ep = EPaper::new(&panel, spi, power, busy, reset, dc, Delay::new());
     // let len = size.width / 8 * size.height * 2;
     // 0b00100111, 00 10 01 11, white gray1 gray2 black
     // fill white
@@ -204,22 +518,18 @@ fn _backup_for_img_display() -> ! {
     let mut canvas = Canvas::new(&size);
     let bmp = load_img();
     // debug_alloc("load img");
-    let x_start = ((size.width - bmp.size().width) / 2) as i32;
-    let y_start = ((size.height - bmp.size().height) / 2) as i32;
-    let point = Point::new(x_start, y_start);
-    info!("image start point: {:?}", point);
+    let center = canvas.bounding_box().center();
+    info!("image center point: {:?}", center);
     // force drop img
-    let img = Image::new(&bmp, point);
+    let img = Image::with_center(&bmp, center);
     info!("image size: {:?}", img.bounding_box());
     info!("pre draw to canvas");
     debug_alloc("new img");
     img.draw(&mut canvas).unwrap();
     debug_alloc("draw canvas");
     info!("draw to canvas");
-    let data = canvas.render_gray();
-    debug_alloc("render");
-    info!("data len: {}", data.len());
-    ep.display_gray4(data.as_slice()).unwrap();
+    // gray4 or black white if the heap is low
+    ep.display_canvas_gray4(&canvas).unwrap();
     debug_alloc("display");
     ep.halt().unwrap();
     info!("display finish");
@@ -279,7 +589,8 @@ pub fn create_network_stack<'a>(
     mut device: wifi::WifiDevice<'a>,
     ss: iface::SocketSet<'a>,
     rand: u32,
-) -> Stack<'a, wifi::WifiDevice<'a>> {
+    cfg: &nvs::Config,
+) -> Result<Stack<'a, wifi::WifiDevice<'a>>, Error> {
     let interface = iface::Interface::new(
         iface::Config::new(wire::HardwareAddress::Ethernet(
             wire::EthernetAddress::from_bytes(&device.mac_address()),
@@ -291,40 +602,103 @@ pub fn create_network_stack<'a>(
         ),
     );
 
-    controller
-        .set_power_saving(esp_wifi::config::PowerSaveMode::None)
-        .unwrap();
+    controller.set_power_saving(esp_wifi::config::PowerSaveMode::None)?;
 
     let now = || time::Instant::now().duration_since_epoch().as_millis();
     let stack = Stack::new(interface, device, ss, now, rand);
 
-    let client_config = wifi::Configuration::Client(wifi::ClientConfiguration {
-        ssid: config::SSID.into(),
-        password: config::PASSWORD.into(),
+    let client_config = client_config(cfg);
+    let delay = Delay::new();
+    let mut retry_delay_ms = config::WIFI_RETRY_DELAY_MS;
+    let mut attempt = 1;
+    loop {
+        let Err(err) = connect_wifi(controller, &stack, &client_config, true) else {
+            return Ok(stack);
+        };
+        warn!(
+            "connect wifi error, attempt {}/{}: {:?}",
+            attempt,
+            config::WIFI_CONNECT_ATTEMPTS,
+            err
+        );
+        // tear down, the next attempt configures the controller again
+        radio_off(controller);
+        if attempt >= config::WIFI_CONNECT_ATTEMPTS {
+            return Err(err);
+        }
+        info!("retry wifi in {}ms", retry_delay_ms);
+        delay.delay_millis(retry_delay_ms);
+        retry_delay_ms = retry_delay_ms.saturating_mul(2);
+        attempt += 1;
+    }
+}
+
+fn client_config(cfg: &nvs::Config) -> wifi::Configuration {
+    wifi::Configuration::Client(wifi::ClientConfiguration {
+        ssid: cfg.ssid.as_str().into(),
+        password: cfg.password.as_str().into(),
         ..Default::default()
-    });
-    let res = controller.set_configuration(&client_config);
-    debug!("wifi_set_configuration returned {:?}", res);
+    })
+}
+
+/// disconnect and stop the controller, which powers the radio down
+fn radio_off(controller: &mut wifi::WifiController) {
+    if let Err(err) = controller.disconnect() {
+        debug!("disconnect wifi error: {:?}", err);
+    }
+    if let Err(err) = controller.stop() {
+        debug!("stop wifi error: {:?}", err);
+    }
+}
 
-    controller.start().unwrap();
+/// power the radio up again after `radio_off` and wait for a fresh dhcp lease
+///
+/// the lease may have expired while the radio was off, so the dhcp state is reset
+/// instead of trusting the old address. sockets live in the stack and stay bound,
+/// so the sink can send right after this returns.
+/// the radio is left off on error, the caller keeps its metrics queued
+fn radio_on(
+    controller: &mut wifi::WifiController,
+    stack: &Stack<'_, wifi::WifiDevice<'_>>,
+    client_config: &wifi::Configuration,
+) -> Result<(), Error> {
+    stack.reset();
+    connect_wifi(controller, stack, client_config, false).inspect_err(|_| radio_off(controller))
+}
+
+/// configure and connect wifi, then wait for dhcp,
+/// return `Error::Timeout` if not connected or no ip in 10s each
+fn connect_wifi(
+    controller: &mut wifi::WifiController,
+    stack: &Stack<'_, wifi::WifiDevice<'_>>,
+    client_config: &wifi::Configuration,
+    scan: bool,
+) -> Result<(), Error> {
+    const WAIT_COUNT: u32 = 10;
+    controller.set_configuration(client_config)?;
+
+    controller.start()?;
     debug!("is wifi started: {:?}", controller.is_started());
 
-    info!("scan wifi");
-    let res = controller.scan_n(10).unwrap();
-    for ap in res {
-        info!("{:?}", ap);
+    // only log the aps once, a scan takes about 2s of radio time
+    if scan {
+        info!("scan wifi");
+        let res = controller.scan_n(10)?;
+        for ap in res {
+            info!("{:?}", ap);
+        }
     }
 
     debug!("capabilities: {:?}", controller.capabilities());
-    controller.connect().unwrap();
+    controller.connect()?;
     info!("connect to wifi");
 
     let delay = Delay::new();
     // wait to get connected
     debug!("wait to get connected");
-    let mut wait_count = 10;
+    let mut wait_count = WAIT_COUNT;
     loop {
-        if controller.is_connected().unwrap() {
+        if controller.is_connected()? {
             info!("wifi connected");
             break;
         } else {
@@ -334,7 +708,7 @@ pub fn create_network_stack<'a>(
         wait_count -= 1;
         if wait_count == 0 {
             warn!("wait wifi connected timeout");
-            break;
+            return Err(Error::Timeout);
         }
     }
     info!("setting dhcp");
@@ -345,32 +719,34 @@ pub fn create_network_stack<'a>(
         stack.work();
 
         if stack.is_iface_up() {
-            debug!("stack ready, ip info {:?}", stack.get_ip_info().unwrap());
-            break;
+            debug!("stack ready, ip info {:?}", stack.get_ip_info());
+            return Ok(());
         }
         debug!("stack not ready, wait 1s, count {}", count);
         count += 1;
-        if count > 10 {
+        if count > WAIT_COUNT {
             warn!("wait stack ready timeout");
-            break;
+            return Err(Error::Timeout);
         }
         delay.delay_millis(1000);
     }
-    stack
 }
 
-pub fn send_metric(
-    socket: &mut UdpSocket<wifi::WifiDevice>,
-    m: &MeasureResult,
-) -> Result<(), IoError> {
-    let addr = blocking_network_stack::ipv4::Ipv4Addr::from(net::parse_ip(config::METRIC_SERVER));
-    let port = config::METRIC_PORT;
-    debug!("start to send metric to udp://{}:{}", addr, port);
-    let data = [
-        m.temp.to_be_bytes().as_slice(),
-        m.hum.to_be_bytes().as_slice(),
-        m.co2_ppm.to_be_bytes().as_slice(),
-    ]
-    .concat();
-    socket.send(addr.into(), port, data.as_slice())
+/// show `title` and `err` on the panel, then restart to try again
+fn restart_with_message(ep: &EspEPaper, title: &str, err: &Error) -> ! {
+    let message = format!("{}", err);
+    if let Err(err) = ep.display_message(&[title, message.as_str(), "restart in 30s"]) {
+        warn!("display message error: {:?}", err);
+    }
+    if let Err(err) = ep.halt() {
+        warn!("halt e-paper error: {:?}", err);
+    }
+    restart(title, err)
+}
+
+/// restart after `RESTART_DELAY_MS`, the panel is left as is, e.g. showing the self test
+fn restart(title: &str, err: &Error) -> ! {
+    warn!("{}: {}, restart in {}ms", title, err, RESTART_DELAY_MS);
+    Delay::new().delay_millis(RESTART_DELAY_MS);
+    esp_hal::system::software_reset()
 }
@@ -0,0 +1,72 @@
+use esp_hal::delay::Delay;
+use esp_hal::gpio::Input;
+
+// the level must keep the same for this many polls to be accepted
+const DEBOUNCE_COUNT: u8 = 3;
+const POLL_INTERVAL_MS: u32 = 10;
+
+/// push button connected to ground, the pin should be pulled up
+///
+/// debounce: the pin is sampled every 10ms, a new level is accepted only if
+/// it is read 3 times in a row, so the bounce in the first ~30ms is ignored.
+/// a press is reported once on the accepted high -> low edge
+pub struct Button<'a> {
+    input: Input<'a>,
+    // accepted level, true for pressed
+    pressed: bool,
+    // polls with a level different from the accepted one
+    count: u8,
+}
+
+impl<'a> Button<'a> {
+    pub fn new(input: Input<'a>) -> Self {
+        Button {
+            input,
+            pressed: false,
+            count: 0,
+        }
+    }
+
+    /// sample the pin once, return true when the button is just pressed
+    pub fn poll(&mut self) -> bool {
+        let pressed = self.input.is_low();
+        if pressed == self.pressed {
+            self.count = 0;
+            return false;
+        }
+        self.count += 1;
+        if self.count < DEBOUNCE_COUNT {
+            return false;
+        }
+        self.count = 0;
+        self.pressed = pressed;
+        pressed
+    }
+
+    /// whether the button is kept pressed for `hold_ms`, e.g. a hold at boot.
+    /// return immediately if it is not pressed
+    pub fn is_held(&self, delay: &Delay, hold_ms: u32) -> bool {
+        let mut held = 0;
+        while held < hold_ms {
+            if self.input.is_high() {
+                return false;
+            }
+            delay.delay_millis(POLL_INTERVAL_MS);
+            held += POLL_INTERVAL_MS;
+        }
+        true
+    }
+
+    /// wait up to `timeout_ms` for a press, return the waited ms if pressed
+    pub fn wait_press(&mut self, delay: &Delay, timeout_ms: u32) -> Option<u32> {
+        let mut waited = 0;
+        while waited < timeout_ms {
+            if self.poll() {
+                return Some(waited);
+            }
+            delay.delay_millis(POLL_INTERVAL_MS);
+            waited += POLL_INTERVAL_MS;
+        }
+        None
+    }
+}
@@ -1,46 +1,91 @@
+use crate::e_paper::Rotation;
 use crate::error::Error;
 use crate::utils::DebugPrinter;
-use alloc::string::ToString;
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use alloc::{format, vec};
 use embedded_graphics::draw_target::DrawTarget;
-use embedded_graphics::geometry::{Dimensions, Point, Size};
-use embedded_graphics::mono_font::MonoTextStyleBuilder;
+use embedded_graphics::geometry::{Dimensions, OriginDimensions, Point, Size};
+use embedded_graphics::image::Image;
+use embedded_graphics::mono_font::{MonoFont, MonoTextStyleBuilder};
 use embedded_graphics::pixelcolor::raw::ToBytes;
-use embedded_graphics::pixelcolor::{Gray4, GrayColor};
-use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::pixelcolor::{Gray4, GrayColor, Rgb888};
+use embedded_graphics::primitives::{
+    Line, PointsIter, Primitive, PrimitiveStyle, Rectangle, Triangle,
+};
 use embedded_graphics::text::renderer::TextRenderer;
 use embedded_graphics::text::Baseline;
-use embedded_graphics::{mono_font, Pixel};
+use embedded_graphics::{mono_font, Drawable, Pixel};
 use log::{debug, warn};
+use tinybmp::Bmp;
 
-use crate::scd41::MeasureResult;
+use crate::net::NetStatus;
+use crate::scd41::{MeasureResult, TempUnit};
+use crate::smooth::Smoother;
 
-enum Gray2Color {
+/// 2 bit gray stored in `Canvas` and sent to the panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gray2Color {
     Black = 0b11,
     DarkGray = 0b01,
     LightGray = 0b10,
     White = 0b00,
 }
 
-impl Into<u8> for Gray2Color {
-    fn into(self) -> u8 {
-        self as u8
+impl Gray2Color {
+    /// map 4 bit gray to the nearest 2 bit gray
+    pub fn from_gray4(color: Gray4) -> Self {
+        match color.luma() {
+            0..=3 => Gray2Color::Black,
+            4..=7 => Gray2Color::DarkGray,
+            8..=11 => Gray2Color::LightGray,
+            _ => Gray2Color::White,
+        }
+    }
+
+    /// gray shown on the panel, for previews on other displays
+    fn to_rgb(value: u8) -> Rgb888 {
+        match value & 0b11 {
+            0b11 => Rgb888::new(0x00, 0x00, 0x00),
+            0b01 => Rgb888::new(0x55, 0x55, 0x55),
+            0b10 => Rgb888::new(0xaa, 0xaa, 0xaa),
+            _ => Rgb888::new(0xff, 0xff, 0xff),
+        }
     }
 }
 
+impl From<Gray2Color> for u8 {
+    fn from(color: Gray2Color) -> u8 {
+        color as u8
+    }
+}
+
+/// contrast curve for `Canvas::with_gray_curve`, darkens the dark and lightens the light grays,
+/// images look less washed out on the panel
+pub const DEFAULT_GRAY_CURVE: [u8; 16] = [0, 0, 1, 2, 3, 5, 6, 7, 8, 9, 10, 12, 13, 14, 15, 15];
+
 pub struct Canvas {
+    // size of the panel, not swapped by rotation
     height: u32,
     width: u32,
+    rotation: Rotation,
+    // map colors by absolute luminance instead of the colors drawn
+    fixed_palette: bool,
+    // applied to the colors drawn before they are mapped to 2 bit gray
+    curve: Option<[u8; 16]>,
     colors: Vec<u8>,
-    pixels: Vec<Vec<u8>>,
+    // one allocation for all pixels, `width` ram rows of `height` pixels, see `index`
+    pixels: Vec<u8>,
 }
 
-impl Dimensions for Canvas {
-    fn bounding_box(&self) -> Rectangle {
-        Rectangle {
-            top_left: Point { x: 0, y: 0 },
-            size: Size::new(self.width, self.height),
+// `Dimensions` comes from the blanket impl of `OriginDimensions`
+impl OriginDimensions for Canvas {
+    fn size(&self) -> Size {
+        if self.rotation.is_portrait() {
+            Size::new(self.height, self.width)
+        } else {
+            Size::new(self.width, self.height)
         }
     }
 }
@@ -56,8 +101,28 @@ impl DrawTarget for Canvas {
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
         DebugPrinter::new("draw iter".to_string());
+        let bounding_box = self.bounding_box();
+        let curve = self.curve;
+        let pixels = pixels.into_iter().map(|Pixel(point, color)| match curve {
+            Some(curve) => Pixel(point, Gray4::new(curve[color.luma() as usize].min(15))),
+            None => Pixel(point, color),
+        });
+        if self.fixed_palette {
+            pixels.into_iter().for_each(|Pixel(point, color)| {
+                if !bounding_box.contains(point) {
+                    return;
+                }
+                let index = self.index(self.to_ram(point));
+                self.pixels[index] = Gray2Color::from_gray4(color).into();
+            });
+            return Ok(());
+        }
         pixels.into_iter().for_each(|Pixel(point, color)| {
             // debug!("color: {:?}, point: {:?}", color, point);
+            // skip pixels out of canvas, as draw targets are expected to do
+            if !bounding_box.contains(point) {
+                return;
+            }
             let mut data = color.to_be_bytes()[0];
             // store in first 4 bit to ensure min value is not 0
             data += 0x10;
@@ -66,13 +131,9 @@ impl DrawTarget for Canvas {
                 self.colors.push(data);
                 debug!("color len: {}", self.colors.len());
             }
-            if point.x >= self.pixels.len() as i32 || point.y >= self.pixels[0].len() as i32 {
-                warn!("out of range, color: {:?}, point: {:?}", color, point);
-            }
-            // NOTE: I don't know why but the x-axis is reverted, so we revert it back
-            let x = self.width - point.x as u32 - 1;
+            let index = self.index(self.to_ram(point));
             // debug!("draw ({}, {}): {:04b}", point.x, point.y, data);
-            self.pixels[x as usize][point.y as usize] = data;
+            self.pixels[index] = data;
         });
         // debug!("sort color, color len: {}", self.colors.len());
         self.colors.sort();
@@ -124,29 +185,27 @@ impl DrawTarget for Canvas {
         //   but we cannot collect colors from pixels then do range
         //   because it cannot be used after moved.
         //   neither can we collect all pixels and range twice because of the memory limit
-        self.pixels.iter_mut().for_each(|row| {
-            row.iter_mut().for_each(|pixel| {
-                let color;
-                match *pixel {
-                    p if p == black => {
-                        color = Gray2Color::Black;
-                    }
-                    p if p == light_gray => {
-                        color = Gray2Color::LightGray;
-                    }
-                    p if p == dark_gray => {
-                        color = Gray2Color::DarkGray;
-                    }
-                    p if p == white => {
-                        color = Gray2Color::White;
-                    }
-                    _ => {
-                        // ignore
-                        return;
-                    }
+        self.pixels.iter_mut().for_each(|pixel| {
+            let color;
+            match *pixel {
+                p if p == black => {
+                    color = Gray2Color::Black;
                 }
-                *pixel = color.into();
-            })
+                p if p == light_gray => {
+                    color = Gray2Color::LightGray;
+                }
+                p if p == dark_gray => {
+                    color = Gray2Color::DarkGray;
+                }
+                p if p == white => {
+                    color = Gray2Color::White;
+                }
+                _ => {
+                    // ignore
+                    return;
+                }
+            }
+            *pixel = color.into();
         });
         Ok(())
     }
@@ -154,32 +213,165 @@ impl DrawTarget for Canvas {
 
 impl Canvas {
     pub fn new(size: &Size) -> Self {
+        Self::with_rotation(size, Rotation::Rotate0)
+    }
+
+    /// create canvas for the panel of `size`, drawing coordinates are rotated by `rotation`
+    ///
+    /// the panel ram layout is the same for all rotations, `EPaper::set_rotation`
+    /// only sets the rotation of the canvas it draws itself
+    pub fn with_rotation(size: &Size, rotation: Rotation) -> Self {
         Canvas {
             width: size.width,
             height: size.height,
+            rotation,
+            fixed_palette: false,
+            curve: None,
             colors: Vec::new(),
-            pixels: vec![vec![0; size.height as usize]; size.width as usize],
+            pixels: vec![0; (size.width * size.height) as usize],
         }
     }
 }
 
 impl Canvas {
-    /// render gray pixels to width*height/4 sized vector
+    /// map colors to 2 bit gray by absolute luminance (0-3, 4-7, 8-11, 12-15),
+    /// so the same color always gets the same output across frames.
+    /// the default adaptive palette spreads the colors drawn over the 4 levels,
+    /// which suits images better
+    pub fn with_fixed_palette(mut self) -> Self {
+        self.fixed_palette = true;
+        self
+    }
+
+    /// map every color drawn by `curve` before it is quantized to 2 bit gray,
+    /// index and value are 4 bit luma, e.g. `DEFAULT_GRAY_CURVE` to boost the contrast of photos.
+    /// the identity curve `[0, 1, .., 15]` keeps the colors as is
+    pub fn with_gray_curve(mut self, curve: [u8; 16]) -> Self {
+        self.curve = Some(curve);
+        self
+    }
+
+    /// fill the whole canvas with `color` and forget the colors drawn before,
+    /// so the canvas can be reused for the next frame
+    pub fn clear(&mut self, color: Gray4) {
+        let data: u8 = Gray2Color::from_gray4(color).into();
+        self.pixels.fill(data);
+        self.colors.clear();
+    }
+
+    /// alias of `clear`
+    pub fn fill(&mut self, color: Gray4) {
+        self.clear(color);
+    }
+
+    /// fill `rect` in drawing coordinates with `color`, the part out of the canvas is clipped.
+    /// e.g. clear a text line before drawing it again for `EPaper::display_partial_region`
+    pub fn clear_region(&mut self, rect: Rectangle, color: Gray4) {
+        let color = Gray2Color::from_gray4(color);
+        rect.intersection(&self.bounding_box())
+            .points()
+            .for_each(|point| self.set_pixel(point, color));
+    }
+
+    /// swap black and white, dark and light gray in `rect`, the part out of the canvas is clipped
+    pub fn invert_region(&mut self, rect: Rectangle) {
+        rect.intersection(&self.bounding_box())
+            .points()
+            .for_each(|point| {
+                let index = self.index(self.to_ram(point));
+                self.pixels[index] ^= 0b11;
+            });
+    }
+
+    /// draw target that dithers to 2 bit gray, for photos, e.g.
+    /// `Image::new(&bmp, point).draw(&mut canvas.dithered())`
+    pub fn dithered(&mut self) -> Dithered<'_> {
+        let width = self.size().width as usize;
+        Dithered {
+            canvas: self,
+            row: None,
+            current: vec![0; width + 2],
+            next: vec![0; width + 2],
+        }
+    }
+
+    /// set a pixel without going through `draw_iter`, pixels out of the canvas are ignored
+    ///
+    /// NOTE: with the default adaptive palette, `draw_iter` maps the colors it has seen
+    /// over the whole canvas, call `clear` first if the canvas was drawn with other colors
+    pub fn set_pixel(&mut self, point: Point, color: Gray2Color) {
+        if !self.bounding_box().contains(point) {
+            return;
+        }
+        let index = self.index(self.to_ram(point));
+        self.pixels[index] = color.into();
+    }
+
+    /// raw value of a pixel, 2 bit gray once normalized, see `Gray2Color`.
+    /// `None` if out of the canvas
+    pub fn get_pixel(&self, point: Point) -> Option<u8> {
+        if !self.bounding_box().contains(point) {
+            return None;
+        }
+        Some(self.pixels[self.index(self.to_ram(point))])
+    }
+
+    /// map drawing coordinates to panel ram coordinates
+    ///
+    /// in landscape the ram y-axis starts from the right edge of the panel,
+    /// so x is reverted. 180 and 270 turn 0 and 90 around in the ram,
+    /// so the padding of a short side stays at the end of each ram row
+    fn to_ram(&self, point: Point) -> (i32, i32) {
+        let (width, height) = (self.width as i32, self.height as i32);
+        match self.rotation {
+            Rotation::Rotate0 => (width - point.x - 1, point.y),
+            Rotation::Rotate90 => (width - point.y - 1, height - point.x - 1),
+            Rotation::Rotate180 => (point.x, height - point.y - 1),
+            Rotation::Rotate270 => (point.y, point.x),
+        }
+    }
+
+    /// map panel ram coordinates to drawing coordinates, reverse of `to_ram`
+    fn ram_to_point(&self, (x, y): (i32, i32)) -> Point {
+        let (width, height) = (self.width as i32, self.height as i32);
+        match self.rotation {
+            Rotation::Rotate0 => Point::new(width - x - 1, y),
+            Rotation::Rotate90 => Point::new(height - y - 1, width - x - 1),
+            Rotation::Rotate180 => Point::new(x, height - y - 1),
+            Rotation::Rotate270 => Point::new(y, x),
+        }
+    }
+
+    /// position of a pixel in ram coordinates in `pixels`, a ram row is contiguous
+    fn index(&self, (x, y): (i32, i32)) -> usize {
+        x as usize * self.height as usize + y as usize
+    }
+
+    /// ram rows of `height` pixels
+    fn rows(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.pixels.chunks(self.height.max(1) as usize)
+    }
+
+    /// render gray pixels to width*ceil(height/8)*2 sized vector
+    ///
+    /// a ram row holds whole bytes of 8 pixels, so if height is not a multiple of 8,
+    /// e.g. 122 of the 2.13 inch panel, each row is padded with white
     pub fn render_gray(&self) -> Vec<u8> {
         DebugPrinter::new("render gray".to_string());
         // 1 byte -> 2 bit
         // 4 byte -> 1 byte
-        let mut data = Vec::new();
-        self.pixels.iter().for_each(|row| {
-            row.chunks_exact(4).for_each(|chunk| {
-                let d = (chunk[0] & 0b11) << 6
-                    | (chunk[1] & 0b11) << 4
-                    | (chunk[2] & 0b11) << 2
-                    | (chunk[3] & 0b11);
-                data.push(d);
+        let required_len = (self.width * self.padded_height() / 4) as usize;
+        let mut data = Vec::with_capacity(required_len);
+        self.padded_rows().for_each(|row| {
+            let mut d = 0u8;
+            row.enumerate().for_each(|(i, pixel)| {
+                d = d << 2 | (pixel & 0b11);
+                if i % 4 == 3 {
+                    data.push(d);
+                    d = 0;
+                }
             });
         });
-        let required_len = (self.width * self.height / 4) as usize;
         assert_eq!(
             data.len(),
             required_len,
@@ -190,14 +382,44 @@ impl Canvas {
         data
     }
 
-    /// render black white pixels to width*height/8 sized vector
+    /// draw the canvas as the panel shows it onto an rgb `target`,
+    /// e.g. `SimulatorDisplay` of embedded-graphics-simulator to preview layouts
+    pub fn draw_preview<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb888>,
+    {
+        let pixels = self.rows().enumerate().flat_map(|(x, row)| {
+            row.iter().enumerate().map(move |(y, pixel)| {
+                let point = self.ram_to_point((x as i32, y as i32));
+                Pixel(point, Gray2Color::to_rgb(*pixel))
+            })
+        });
+        target.draw_iter(pixels)
+    }
+
+    /// render black white pixels to width*ceil(height/8) sized vector, rows padded with white
     pub fn render_black_white(&self) -> Vec<u8> {
+        // black and dark gray are black
+        self.render_black_white_threshold(2)
+    }
+
+    /// render black white pixels to width*ceil(height/8) sized vector, rows padded with white,
+    /// pixels with darkness >= `level` are black.
+    /// darkness is 0 for white, 1 for light gray, 2 for dark gray and 3 for black
+    pub fn render_black_white_threshold(&self, level: u8) -> Vec<u8> {
         DebugPrinter::new("render black white".to_string());
         // 1 byte -> 1 bit
         // 8 byte -> 1 byte
-        let mut data = Vec::new();
+        let required_len = (self.width * self.padded_height() / 8) as usize;
+        let mut data = Vec::with_capacity(required_len);
         let black_white = |c: u8| -> u8 {
-            if c == 1 {
+            let darkness = match c & 0b11 {
+                0b11 => 3,
+                0b01 => 2,
+                0b10 => 1,
+                _ => 0,
+            };
+            if darkness >= level {
                 // black
                 0
             } else {
@@ -205,20 +427,16 @@ impl Canvas {
                 1
             }
         };
-        self.pixels.iter().for_each(|row| {
-            row.chunks_exact(8).for_each(|chunk| {
-                let d = black_white(chunk[0] & 1) << 7
-                    | black_white(chunk[1] & 1) << 6
-                    | black_white(chunk[2] & 1) << 5
-                    | black_white(chunk[3] & 1) << 4
-                    | black_white(chunk[4] & 1) << 3
-                    | black_white(chunk[5] & 1) << 2
-                    | black_white(chunk[6] & 1) << 1
-                    | black_white(chunk[7] & 1);
-                data.push(d);
+        self.padded_rows().for_each(|row| {
+            let mut d = 0u8;
+            row.enumerate().for_each(|(i, pixel)| {
+                d = d << 1 | black_white(pixel);
+                if i % 8 == 7 {
+                    data.push(d);
+                    d = 0;
+                }
             });
         });
-        let required_len = (self.width * self.height / 8) as usize;
         assert_eq!(
             data.len(),
             required_len,
@@ -229,44 +447,1085 @@ impl Canvas {
         data
     }
 
+    /// height rounded up to whole bytes of 8 pixels
+    fn padded_height(&self) -> u32 {
+        self.height.div_ceil(8) * 8
+    }
+
+    /// ram rows of `padded_height` pixels, padded with white
+    fn padded_rows(&self) -> impl Iterator<Item = impl Iterator<Item = u8> + '_> + '_ {
+        let len = self.padded_height() as usize;
+        let white: u8 = Gray2Color::White.into();
+        self.rows().map(move |row| {
+            row.iter()
+                .copied()
+                .chain(core::iter::repeat(white))
+                .take(len)
+        })
+    }
+
+    /// copy pixels of `canvas` to `point`, both in ram coordinates,
+    /// pixels out of this canvas are clipped.
+    /// pixels are copied as is, they are already normalized to 2 bit gray by `draw_iter`
     pub fn draw_at(&mut self, canvas: Canvas, point: Point) {
-        canvas.pixels.iter().enumerate().for_each(|(x, row)| {
+        let (width, height) = (self.width as i32, self.height as i32);
+        canvas.rows().enumerate().for_each(|(x, row)| {
+            let x = x as i32 + point.x;
+            if !(0..width).contains(&x) {
+                return;
+            }
             row.iter().enumerate().for_each(|(y, pixel)| {
-                self.pixels[x + point.x as usize][y + point.y as usize] = *pixel;
+                let y = y as i32 + point.y;
+                if !(0..height).contains(&y) {
+                    return;
+                }
+                let index = self.index((x, y));
+                self.pixels[index] = *pixel;
             })
         });
     }
 
-    pub fn draw_text(&mut self, text: &str, point: Point) {
-        let font = mono_font::ascii::FONT_10X20;
-        let color = Gray4::BLACK;
+    /// draw rectangle border with 1 pixel stroke
+    pub fn draw_rect(&mut self, rect: Rectangle, color: Gray4) -> Result<(), Error> {
+        rect.into_styled(PrimitiveStyle::with_stroke(color, 1))
+            .draw(self)
+    }
+
+    /// draw horizontal line from `start` to the right
+    pub fn draw_hline(&mut self, start: Point, len: u32, color: Gray4) -> Result<(), Error> {
+        if len == 0 {
+            return Ok(());
+        }
+        self.draw_line(start, start + Point::new(len as i32 - 1, 0), color)
+    }
+
+    /// draw vertical line from `start` to the bottom
+    pub fn draw_vline(&mut self, start: Point, len: u32, color: Gray4) -> Result<(), Error> {
+        if len == 0 {
+            return Ok(());
+        }
+        self.draw_line(start, start + Point::new(0, len as i32 - 1), color)
+    }
+
+    /// draw line from `start` to `end` with 1 pixel stroke
+    pub fn draw_line(&mut self, start: Point, end: Point, color: Gray4) -> Result<(), Error> {
+        Line::new(start, end)
+            .into_styled(PrimitiveStyle::with_stroke(color, 1))
+            .draw(self)
+    }
+
+    /// draw black text in `FONT_10X20`
+    pub fn draw_text(&mut self, text: &str, point: Point) -> Result<(), Error> {
+        self.draw_text_styled(text, point, &mono_font::ascii::FONT_10X20, Gray4::BLACK)
+    }
+
+    /// draw text with its baseline at the bottom of `point`,
+    /// fails like `draw_iter` if `color` is a fifth color on the adaptive palette
+    pub fn draw_text_styled(
+        &mut self,
+        text: &str,
+        point: Point,
+        font: &MonoFont,
+        color: Gray4,
+    ) -> Result<(), Error> {
         let style = MonoTextStyleBuilder::new()
             .text_color(color)
-            .font(&font)
+            .font(font)
             .build();
-        style
-            .draw_string(text, point, Baseline::Bottom, self)
-            .unwrap();
+        style.draw_string(text, point, Baseline::Bottom, self)?;
+        Ok(())
+    }
+}
+
+/// air quality level by co2 concentration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Co2Level {
+    Good,
+    Fair,
+    Poor,
+}
+
+impl Co2Level {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Co2Level::Good => "GOOD",
+            Co2Level::Fair => "FAIR",
+            Co2Level::Poor => "POOR",
+        }
+    }
+}
+
+/// co2 thresholds in ppm, below `fair` is good, above `poor` is poor
+#[derive(Debug, Clone, Copy)]
+pub struct Co2Thresholds {
+    pub fair: u16,
+    pub poor: u16,
+}
+
+impl Default for Co2Thresholds {
+    fn default() -> Self {
+        Co2Thresholds {
+            fair: 800,
+            poor: 1200,
+        }
+    }
+}
+
+impl Co2Thresholds {
+    pub fn level(&self, co2_ppm: u16) -> Co2Level {
+        if co2_ppm < self.fair {
+            Co2Level::Good
+        } else if co2_ppm <= self.poor {
+            Co2Level::Fair
+        } else {
+            Co2Level::Poor
+        }
+    }
+}
+
+/// direction of co2 compared to the average of recent values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Co2Trend {
+    Rising,
+    Falling,
+    Stable,
+}
+
+impl Co2Trend {
+    /// compare `current` to `average`, changes within `deadband` ppm are stable
+    pub fn classify(current: u16, average: u16, deadband: u16) -> Self {
+        if current > average.saturating_add(deadband) {
+            Co2Trend::Rising
+        } else if current < average.saturating_sub(deadband) {
+            Co2Trend::Falling
+        } else {
+            Co2Trend::Stable
+        }
+    }
+}
+
+/// what `Screen` shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScreenMode {
+    /// numbers and co2 level
+    Dashboard,
+    /// co2 history in full screen
+    Sparkline,
+    /// numbers, co2 level and a small sparkline
+    #[default]
+    AllInfo,
+}
+
+impl ScreenMode {
+    pub fn next(&self) -> Self {
+        match self {
+            ScreenMode::Dashboard => ScreenMode::Sparkline,
+            ScreenMode::Sparkline => ScreenMode::AllInfo,
+            ScreenMode::AllInfo => ScreenMode::Dashboard,
+        }
+    }
+}
+
+/// position and font of a text field of `Screen`
+#[derive(Clone, Copy)]
+pub struct FieldLayout {
+    /// bottom left of the text
+    pub anchor: Point,
+    pub font: &'static MonoFont<'static>,
+    /// drawn before the value, e.g. `Temp: `
+    pub label: Option<&'static str>,
+}
+
+impl FieldLayout {
+    pub fn new(
+        anchor: Point,
+        font: &'static MonoFont<'static>,
+        label: Option<&'static str>,
+    ) -> Self {
+        FieldLayout {
+            anchor,
+            font,
+            label,
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas, value: &str) -> Result<(), Error> {
+        let text = format!("{}{}", self.label.unwrap_or_default(), value);
+        canvas.draw_text_styled(text.as_str(), self.anchor, self.font, Gray4::BLACK)
+    }
+}
+
+/// where `Screen` draws each field, in drawing coordinates
+#[derive(Clone, Copy)]
+pub struct ScreenLayout {
+    pub temp: FieldLayout,
+    pub hum: FieldLayout,
+    pub co2: FieldLayout,
+    /// the co2 level label
+    pub level: FieldLayout,
+    /// top left of the 12x12 trend arrow
+    pub trend: Point,
+    /// area of the sparkline in `ScreenMode::AllInfo`
+    pub plot: Rectangle,
+}
+
+impl ScreenLayout {
+    /// default layout for a screen of `size`, the size after rotation, e.g. `Canvas::size`
+    pub fn for_size(size: Size) -> Self {
+        if size.width >= size.height {
+            Self::landscape(size)
+        } else {
+            Self::portrait(size)
+        }
+    }
+
+    /// 2 columns of numbers with the sparkline at the bottom right, made for 296x128
+    pub fn landscape(size: Size) -> Self {
+        let font = &mono_font::ascii::FONT_10X20;
+        ScreenLayout {
+            temp: FieldLayout::new(Point::new(20, 50), font, Some("Temp: ")),
+            hum: FieldLayout::new(Point::new(160, 50), font, Some("Hum: ")),
+            co2: FieldLayout::new(Point::new(20, 100), font, Some("CO2: ")),
+            level: FieldLayout::new(Point::new(20, 124), font, Some("Air: ")),
+            // right of the co2 text
+            trend: Point::new(154, 86),
+            // right of the co2 text, below the hum text
+            plot: Rectangle::new(
+                Point::new(170, 60),
+                Size::new(
+                    size.width.saturating_sub(180),
+                    size.height.saturating_sub(68),
+                ),
+            ),
+        }
+    }
+
+    /// a column of numbers with the sparkline below, made for 122x250
+    pub fn portrait(size: Size) -> Self {
+        let large = &mono_font::ascii::FONT_10X20;
+        let small = &mono_font::ascii::FONT_9X15;
+        ScreenLayout {
+            // the largest text, without label to fit 122 pixels
+            co2: FieldLayout::new(Point::new(4, 40), large, None),
+            trend: Point::new(100, 26),
+            level: FieldLayout::new(Point::new(4, 62), small, Some("Air: ")),
+            temp: FieldLayout::new(Point::new(4, 84), small, Some("T: ")),
+            hum: FieldLayout::new(Point::new(4, 104), small, Some("H: ")),
+            plot: Rectangle::new(
+                Point::new(4, 114),
+                Size::new(
+                    size.width.saturating_sub(8),
+                    size.height.saturating_sub(122),
+                ),
+            ),
+        }
     }
 }
 
 pub struct Screen {
-    size: Size,
+    // reused across renders to avoid allocating a frame every time
+    canvas: Canvas,
+    // last co2 values for the sparkline, oldest first
+    history: VecDeque<u16>,
+    capacity: usize,
+    layout: ScreenLayout,
+    thresholds: Co2Thresholds,
+    // the trend compares the latest co2 to the average of this many previous values
+    trend_window: usize,
+    trend_deadband: u16,
+    temp_unit: TempUnit,
+    // shown at the top right corner if set
+    net_status: Option<NetStatus>,
+    mode: ScreenMode,
+    // numbers are shown smoothed if set, the history keeps the raw co2
+    smoother: Option<Smoother>,
+    last: MeasureResult,
+    // what the last frame shows, a render showing the same is skipped
+    last_key: Option<String>,
 }
 
 impl Screen {
     pub fn new(size: &Size) -> Self {
-        Screen { size: *size }
-    }
-
-    pub fn render(&mut self, measure_result: &MeasureResult) -> Vec<u8> {
-        let mut canvas = Canvas::new(&self.size);
-        let temp_str = format!("Temp: {:>2.1} C", measure_result.temp);
-        canvas.draw_text(temp_str.as_str(), Point::new(20, 50));
-        let hum_str = format!("Hum: {:>2.1} %", measure_result.hum);
-        canvas.draw_text(hum_str.as_str(), Point::new(160, 50));
-        let co2_str = format!("CO2: {:>4} ppm", measure_result.co2_ppm);
-        canvas.draw_text(co2_str.as_str(), Point::new(20, 100));
-        canvas.render_black_white()
+        Self::with_rotation(size, Rotation::Rotate0)
+    }
+
+    /// the layout follows the rotated size, see `ScreenLayout::for_size`
+    pub fn with_rotation(size: &Size, rotation: Rotation) -> Self {
+        let canvas = Canvas::with_rotation(size, rotation).with_fixed_palette();
+        Screen {
+            layout: ScreenLayout::for_size(canvas.size()),
+            canvas,
+            history: VecDeque::new(),
+            capacity: 0,
+            thresholds: Co2Thresholds::default(),
+            trend_window: 5,
+            trend_deadband: 20,
+            temp_unit: TempUnit::default(),
+            net_status: None,
+            mode: ScreenMode::default(),
+            smoother: None,
+            last: MeasureResult::default(),
+            last_key: None,
+        }
+    }
+
+    /// keep the last `capacity` co2 values and draw them as a sparkline
+    pub fn new_with_history(size: &Size, capacity: usize) -> Self {
+        let mut screen = Self::new(size);
+        screen.capacity = capacity;
+        screen.history = VecDeque::with_capacity(capacity);
+        screen
+    }
+
+    /// set thresholds of the co2 level label
+    pub fn with_thresholds(mut self, thresholds: Co2Thresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    /// set the trend arrow to compare with the average of the last `window` values,
+    /// changes within `deadband` ppm are stable. default is 5 values and 20 ppm,
+    /// `window` of 0 hides the arrow. the values come from the history
+    pub fn with_trend(mut self, window: usize, deadband: u16) -> Self {
+        self.trend_window = window;
+        self.trend_deadband = deadband;
+        self
+    }
+
+    /// show the numbers as exponential moving average with `alpha`, see `Smoother`
+    pub fn with_smoothing(mut self, alpha: f32) -> Self {
+        self.smoother = Some(Smoother::new(alpha));
+        self
+    }
+
+    /// set unit of the temperature text, default is celsius
+    pub fn with_temp_unit(mut self, unit: TempUnit) -> Self {
+        self.temp_unit = unit;
+        self
+    }
+
+    /// place the fields by `layout` instead of the default of the screen size
+    pub fn with_layout(mut self, layout: ScreenLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn layout(&self) -> &ScreenLayout {
+        &self.layout
+    }
+
+    /// set the area of the sparkline
+    pub fn set_plot(&mut self, plot: Rectangle) {
+        self.layout.plot = plot;
+    }
+
+    /// canvas of the last render, e.g. for `Canvas::draw_preview`
+    pub fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+
+    /// show `status` at the top right corner from the next render
+    pub fn set_net_status(&mut self, status: NetStatus) {
+        self.net_status = Some(status);
+    }
+
+    pub fn mode(&self) -> ScreenMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ScreenMode) {
+        self.mode = mode;
+    }
+
+    /// switch to next mode, return the new mode
+    pub fn next_mode(&mut self) -> ScreenMode {
+        self.mode = self.mode.next();
+        self.mode
+    }
+
+    /// record the measure result and render it,
+    /// return none if the screen would show the same text as the last frame,
+    /// so the display update can be skipped
+    pub fn render(&mut self, measure_result: &MeasureResult) -> Option<Vec<u8>> {
+        if self.capacity > 0 {
+            if self.history.len() == self.capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back(measure_result.co2_ppm);
+        }
+        self.last = match self.smoother.as_mut() {
+            Some(smoother) => smoother.update(measure_result),
+            None => *measure_result,
+        };
+        if self.last_key.as_ref() == Some(&self.frame_key()) {
+            debug!("screen not changed, skip render");
+            return None;
+        }
+        Some(self.redraw())
+    }
+
+    /// make the next `render` draw a frame even if nothing changed,
+    /// e.g. after the last frame failed to be displayed
+    pub fn invalidate(&mut self) {
+        self.last_key = None;
+    }
+
+    /// trend of the latest co2 value, none without enough history
+    pub fn trend(&self) -> Option<Co2Trend> {
+        let count = self.history.len().checked_sub(1)?.min(self.trend_window);
+        if count == 0 {
+            return None;
+        }
+        let current = *self.history.back()?;
+        let sum: u32 = self
+            .history
+            .iter()
+            .rev()
+            .skip(1)
+            .take(count)
+            .map(|&value| value as u32)
+            .sum();
+        let average = (sum / count as u32) as u16;
+        Some(Co2Trend::classify(current, average, self.trend_deadband))
+    }
+
+    /// render `bmp` at the center of the screen to a gray4 frame,
+    /// parts out of the screen are clipped
+    pub fn render_image(&mut self, bmp: &Bmp<Gray4>) -> Result<Vec<u8>, Error> {
+        self.canvas.clear(Gray4::WHITE);
+        let center = self.canvas.bounding_box().center();
+        Image::with_center(bmp, center).draw(&mut self.canvas)?;
+        self.last_key = None;
+        Ok(self.canvas.render_gray())
+    }
+
+    /// render the last measure result again, e.g. after mode changed
+    pub fn redraw(&mut self) -> Vec<u8> {
+        let measure_result = self.last;
+        self.last_key = Some(self.frame_key());
+        self.canvas.clear(Gray4::WHITE);
+        match self.mode {
+            ScreenMode::Dashboard => {
+                if let Err(err) = self.draw_numbers(&measure_result) {
+                    warn!("draw numbers error: {:?}", err);
+                }
+            }
+            ScreenMode::Sparkline => {
+                let co2_str = format!("CO2: {:>4} ppm", measure_result.co2_ppm);
+                if let Err(err) = self.canvas.draw_text(co2_str.as_str(), Point::new(20, 24)) {
+                    warn!("draw co2 error: {:?}", err);
+                }
+                let size = self.canvas.size();
+                let plot = Rectangle::new(
+                    Point::new(10, 30),
+                    Size::new(
+                        size.width.saturating_sub(20),
+                        size.height.saturating_sub(40),
+                    ),
+                );
+                if let Err(err) = self.draw_sparkline(plot) {
+                    warn!("draw sparkline error: {:?}", err);
+                }
+            }
+            ScreenMode::AllInfo => {
+                if let Err(err) = self.draw_numbers(&measure_result) {
+                    warn!("draw numbers error: {:?}", err);
+                }
+                if let Err(err) = self.draw_sparkline(self.layout.plot) {
+                    warn!("draw sparkline error: {:?}", err);
+                }
+            }
+        }
+        if let Err(err) = self.draw_net_status() {
+            warn!("draw net status error: {:?}", err);
+        }
+        self.canvas.render_black_white()
+    }
+
+    // everything the frame shows, numbers are rounded as they are drawn
+    fn frame_key(&self) -> String {
+        let m = &self.last;
+        let mut key = format!(
+            "{:?} {:.1} {:.1} {} {:?} {:?}",
+            self.mode,
+            m.temp_in(self.temp_unit),
+            m.hum,
+            m.co2_ppm,
+            self.trend(),
+            self.net_status
+        );
+        if self.mode != ScreenMode::Dashboard {
+            key.push_str(format!(" {:?}", self.history).as_str());
+        }
+        key
+    }
+
+    fn draw_numbers(&mut self, measure_result: &MeasureResult) -> Result<(), Error> {
+        let canvas = &mut self.canvas;
+        let layout = self.layout;
+        let temp_str = format!(
+            "{:>2.1} {}",
+            measure_result.temp_in(self.temp_unit),
+            self.temp_unit.suffix()
+        );
+        layout.temp.draw(canvas, temp_str.as_str())?;
+        let hum_str = format!("{:>2.1} %", measure_result.hum);
+        layout.hum.draw(canvas, hum_str.as_str())?;
+        let co2_str = format!("{:>4} ppm", measure_result.co2_ppm);
+        layout.co2.draw(canvas, co2_str.as_str())?;
+        let level = self.thresholds.level(measure_result.co2_ppm);
+        layout.level.draw(canvas, level.label())?;
+        if let Err(err) = self.draw_trend(layout.trend) {
+            warn!("draw trend error: {:?}", err);
+        }
+        Ok(())
+    }
+
+    /// draw the trend as a 12x12 arrow head from `top_left`, right of the co2 text
+    fn draw_trend(&mut self, top_left: Point) -> Result<(), Error> {
+        let Some(trend) = self.trend() else {
+            return Ok(());
+        };
+        let (x, y) = (top_left.x, top_left.y);
+        let triangle = match trend {
+            Co2Trend::Rising => Triangle::new(
+                Point::new(x + 6, y),
+                Point::new(x, y + 11),
+                Point::new(x + 11, y + 11),
+            ),
+            Co2Trend::Falling => Triangle::new(
+                Point::new(x, y),
+                Point::new(x + 11, y),
+                Point::new(x + 6, y + 11),
+            ),
+            Co2Trend::Stable => Triangle::new(
+                Point::new(x, y),
+                Point::new(x + 11, y + 6),
+                Point::new(x, y + 11),
+            ),
+        };
+        triangle
+            .into_styled(PrimitiveStyle::with_fill(Gray4::BLACK))
+            .draw(&mut self.canvas)
+    }
+
+    fn draw_net_status(&mut self) -> Result<(), Error> {
+        let Some(status) = self.net_status else {
+            return Ok(());
+        };
+        let font = &mono_font::ascii::FONT_6X10;
+        let label = status.label();
+        let text_width = (label.len() as u32 * font.character_size.width) as i32;
+        let x = self.canvas.size().width as i32 - text_width - 4;
+        self.canvas
+            .draw_text_styled(label, Point::new(x, 12), font, Gray4::BLACK)
+    }
+
+    /// draw history scaled to the plot area, from left to right
+    fn draw_sparkline(&mut self, plot: Rectangle) -> Result<(), Error> {
+        if self.capacity < 2 || self.history.is_empty() {
+            return Ok(());
+        }
+        if plot.size.width < 3 || plot.size.height < 3 {
+            return Ok(());
+        }
+        self.canvas.draw_rect(plot, Gray4::BLACK)?;
+        // inside the border
+        let left = plot.top_left.x + 1;
+        let bottom = plot.top_left.y + plot.size.height as i32 - 2;
+        let width = plot.size.width as i32 - 3;
+        let height = plot.size.height as i32 - 3;
+
+        let min = *self.history.iter().min().unwrap() as i32;
+        let max = *self.history.iter().max().unwrap() as i32;
+        let range = (max - min).max(1);
+        let steps = self.capacity as i32 - 1;
+        let to_point = |i: usize, value: u16| {
+            Point::new(
+                left + i as i32 * width / steps,
+                bottom - (value as i32 - min) * height / range,
+            )
+        };
+        let mut last = to_point(0, self.history[0]);
+        for (i, value) in self.history.iter().enumerate().skip(1) {
+            let point = to_point(i, *value);
+            self.canvas.draw_line(last, point, Gray4::BLACK)?;
+            last = point;
+        }
+        Ok(())
+    }
+}
+
+/// floyd-steinberg dithering from `Gray4` to 2 bit gray onto a `Canvas`
+///
+/// the error is carried along the drawing order, so pixels should come row by row
+/// as images draw them. it keeps 2 rows of errors, about 1.2KB for a 296 pixels wide canvas
+pub struct Dithered<'a> {
+    canvas: &'a mut Canvas,
+    row: Option<i32>,
+    // error in 1/16 luma of this row and the next row, shifted by 1 so x - 1 is always valid
+    current: Vec<i16>,
+    next: Vec<i16>,
+}
+
+impl OriginDimensions for Dithered<'_> {
+    fn size(&self) -> Size {
+        self.canvas.size()
+    }
+}
+
+impl DrawTarget for Dithered<'_> {
+    type Color = Gray4;
+    type Error = Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        // distance between 2 levels in 1/16 luma, levels are 0, 5, 10, 15
+        const STEP: i16 = 5 * 16;
+        let bounding_box = self.bounding_box();
+        for Pixel(point, color) in pixels {
+            if !bounding_box.contains(point) {
+                continue;
+            }
+            match self.row {
+                Some(row) if row == point.y => {}
+                Some(row) if row + 1 == point.y => {
+                    core::mem::swap(&mut self.current, &mut self.next);
+                    self.next.fill(0);
+                }
+                _ => {
+                    self.current.fill(0);
+                    self.next.fill(0);
+                }
+            }
+            self.row = Some(point.y);
+
+            let x = point.x as usize + 1;
+            let value = color.luma() as i16 * 16 + self.current[x];
+            let level = ((value + STEP / 2) / STEP).clamp(0, 3);
+            let error = value - level * STEP;
+            let gray = Gray2Color::from_gray4(Gray4::new(level as u8 * 5));
+            self.canvas.set_pixel(point, gray);
+
+            self.current[x + 1] += error * 7 / 16;
+            self.next[x - 1] += error * 3 / 16;
+            self.next[x] += error * 5 / 16;
+            self.next[x + 1] += error / 16;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // landscape canvas of `width` ram rows with `height` pixels each
+    fn canvas(width: u32, height: u32) -> Canvas {
+        Canvas::new(&Size::new(width, height))
+    }
+
+    #[test]
+    fn render_blank_canvas_is_white() {
+        let canvas = canvas(16, 8);
+        assert_eq!(canvas.render_black_white(), vec![0xff; 16]);
+        assert_eq!(canvas.render_gray(), vec![0x00; 32]);
+    }
+
+    #[test]
+    fn render_pixel_at_ram_row_start() {
+        let mut canvas = canvas(8, 8);
+        // x is reverted in landscape, the top left pixel is the first of the last ram row
+        canvas.set_pixel(Point::new(0, 0), Gray2Color::Black);
+
+        let mut black_white = vec![0xff; 8];
+        black_white[7] = 0b0111_1111;
+        assert_eq!(canvas.render_black_white(), black_white);
+
+        let mut gray = vec![0x00; 16];
+        gray[14] = 0b1100_0000;
+        assert_eq!(canvas.render_gray(), gray);
+    }
+
+    #[test]
+    fn render_gray_levels() {
+        let mut canvas = canvas(1, 8);
+        canvas.set_pixel(Point::new(0, 0), Gray2Color::Black);
+        canvas.set_pixel(Point::new(0, 1), Gray2Color::DarkGray);
+        canvas.set_pixel(Point::new(0, 2), Gray2Color::LightGray);
+
+        assert_eq!(canvas.render_gray(), vec![0b1101_1000, 0x00]);
+        // dark gray is black by default
+        assert_eq!(canvas.render_black_white(), vec![0b0011_1111]);
+        assert_eq!(canvas.render_black_white_threshold(1), vec![0b0001_1111]);
+        assert_eq!(canvas.render_black_white_threshold(3), vec![0b0111_1111]);
+    }
+
+    #[test]
+    fn render_pads_rows_with_white() {
+        // 10 pixels per ram row are padded to 16
+        let mut canvas = canvas(2, 10);
+        canvas.set_pixel(Point::new(0, 9), Gray2Color::Black);
+
+        let black_white = canvas.render_black_white();
+        assert_eq!(black_white.len(), 2 * 2);
+        assert_eq!(black_white, vec![0xff, 0xff, 0xff, 0b1011_1111]);
+
+        let gray = canvas.render_gray();
+        assert_eq!(gray.len(), 2 * 4);
+        assert_eq!(gray, vec![0, 0, 0, 0, 0, 0, 0b0011_0000, 0]);
+    }
+
+    #[test]
+    fn render_portrait() {
+        let mut canvas = Canvas::with_rotation(&Size::new(8, 16), Rotation::Rotate90);
+        assert_eq!(canvas.size(), Size::new(16, 8));
+        // the top left pixel is the last of the last ram row
+        canvas.set_pixel(Point::new(0, 0), Gray2Color::Black);
+
+        let mut black_white = vec![0xff; 16];
+        black_white[15] = 0b1111_1110;
+        assert_eq!(canvas.render_black_white(), black_white);
+    }
+
+    #[test]
+    fn ram_to_point_reverses_to_ram() {
+        for rotation in [
+            Rotation::Rotate0,
+            Rotation::Rotate90,
+            Rotation::Rotate180,
+            Rotation::Rotate270,
+        ] {
+            let canvas = Canvas::with_rotation(&Size::new(5, 12), rotation);
+            let size = canvas.size();
+            for y in 0..size.height as i32 {
+                for x in 0..size.width as i32 {
+                    let point = Point::new(x, y);
+                    let (ram_x, ram_y) = canvas.to_ram(point);
+                    assert!((0..5).contains(&ram_x) && (0..12).contains(&ram_y));
+                    assert_eq!(canvas.ram_to_point((ram_x, ram_y)), point, "{:?}", rotation);
+                }
+            }
+        }
+    }
+
+    // luma of a stored 2 bit pixel, the levels `Dithered` picks from
+    fn level_luma(pixel: u8) -> u32 {
+        match pixel {
+            0b11 => 0,
+            0b01 => 5,
+            0b10 => 10,
+            _ => 15,
+        }
+    }
+
+    #[test]
+    fn dithered_mid_gray_mixes_neighbor_levels() {
+        let mut canvas = canvas(16, 16);
+        Rectangle::new(Point::zero(), Size::new(16, 16))
+            .into_styled(PrimitiveStyle::with_fill(Gray4::new(7)))
+            .draw(&mut canvas.dithered())
+            .unwrap();
+
+        let pixels: Vec<u8> = (0..16)
+            .flat_map(|y| (0..16).map(move |x| Point::new(x, y)))
+            .map(|point| canvas.get_pixel(point).unwrap())
+            .collect();
+        // 7 is between dark gray (5) and light gray (10), no black or white is needed
+        let dark = pixels
+            .iter()
+            .filter(|&&p| p == Gray2Color::DarkGray as u8)
+            .count();
+        let light = pixels
+            .iter()
+            .filter(|&&p| p == Gray2Color::LightGray as u8)
+            .count();
+        assert_eq!(dark + light, pixels.len());
+        // 2/5 light gray, some error is lost at the edges
+        assert!((90..=115).contains(&light), "light: {}", light);
+        let mean = pixels.iter().map(|&p| level_luma(p)).sum::<u32>() as f32 / 256.0;
+        assert!((mean - 7.0).abs() < 0.3, "mean: {}", mean);
+    }
+
+    #[test]
+    fn dithered_black_white_is_unchanged() {
+        let mut canvas = canvas(8, 8);
+        let checker = |point: Point| (point.x + point.y) % 2 == 0;
+        let pixels = (0..8)
+            .flat_map(|y| (0..8).map(move |x| Point::new(x, y)))
+            .map(|point| {
+                let color = if checker(point) {
+                    Gray4::BLACK
+                } else {
+                    Gray4::WHITE
+                };
+                Pixel(point, color)
+            });
+        canvas.dithered().draw_iter(pixels).unwrap();
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let point = Point::new(x, y);
+                let expected = if checker(point) {
+                    Gray2Color::Black
+                } else {
+                    Gray2Color::White
+                };
+                assert_eq!(canvas.get_pixel(point), Some(expected as u8), "{:?}", point);
+            }
+        }
+    }
+
+    #[test]
+    fn render_drawn_line() {
+        let mut canvas = canvas(8, 8);
+        // a single color is mapped to black by the adaptive palette
+        canvas
+            .draw_vline(Point::new(1, 0), 8, Gray4::new(6))
+            .unwrap();
+
+        let mut black_white = vec![0xff; 8];
+        black_white[6] = 0x00;
+        assert_eq!(canvas.render_black_white(), black_white);
+
+        let mut gray = vec![0x00; 16];
+        gray[12] = 0xff;
+        gray[13] = 0xff;
+        assert_eq!(canvas.render_gray(), gray);
+    }
+
+    #[test]
+    fn render_drawn_text_matches_pixels() {
+        let mut canvas = canvas(40, 12);
+        canvas
+            .draw_text_styled(
+                "CO2",
+                Point::new(0, 11),
+                &mono_font::ascii::FONT_6X10,
+                Gray4::BLACK,
+            )
+            .unwrap();
+
+        let black_white = canvas.render_black_white();
+        let gray = canvas.render_gray();
+        assert_eq!(black_white.len(), 40 * 2);
+        assert_eq!(gray.len(), 40 * 4);
+
+        let black: u8 = Gray2Color::Black.into();
+        let mut drawn = 0;
+        for point in canvas.bounding_box().points() {
+            let (x, y) = canvas.to_ram(point);
+            let (x, y) = (x as usize, y as usize);
+            let is_black = canvas.get_pixel(point) == Some(black);
+            drawn += is_black as usize;
+            let bit = black_white[x * 2 + y / 8] >> (7 - y % 8) & 1;
+            assert_eq!(bit == 0, is_black, "black white at {:?}", point);
+            let value = gray[x * 4 + y / 4] >> (6 - y % 4 * 2) & 0b11;
+            assert_eq!(value == black, is_black, "gray at {:?}", point);
+        }
+        assert!(drawn > 0, "no text drawn");
+    }
+
+    // layout before the flat buffer, a vec of `height` pixels per ram row with x reverted.
+    // colors are mapped by absolute luminance like `with_fixed_palette`
+    struct PerRowCanvas {
+        rows: Vec<Vec<u8>>,
+    }
+
+    impl OriginDimensions for PerRowCanvas {
+        fn size(&self) -> Size {
+            Size::new(self.rows.len() as u32, self.rows[0].len() as u32)
+        }
+    }
+
+    impl DrawTarget for PerRowCanvas {
+        type Color = Gray4;
+        type Error = Error;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Error>
+        where
+            I: IntoIterator<Item = Pixel<Gray4>>,
+        {
+            let width = self.rows.len() as i32;
+            for Pixel(point, color) in pixels {
+                if self.bounding_box().contains(point) {
+                    let x = (width - point.x - 1) as usize;
+                    self.rows[x][point.y as usize] = Gray2Color::from_gray4(color).into();
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl PerRowCanvas {
+        fn render(&self) -> (Vec<u8>, Vec<u8>) {
+            let mut gray = Vec::new();
+            let mut black_white = Vec::new();
+            for row in &self.rows {
+                for chunk in row.chunks_exact(4) {
+                    gray.push(chunk.iter().fold(0, |d, pixel| d << 2 | (pixel & 0b11)));
+                }
+                // black and dark gray are black
+                for chunk in row.chunks_exact(8) {
+                    black_white.push(chunk.iter().fold(0, |d, pixel| d << 1 | (!pixel & 1)));
+                }
+            }
+            (gray, black_white)
+        }
+    }
+
+    fn draw_sample<D: DrawTarget<Color = Gray4, Error = Error>>(target: &mut D) {
+        let style = |luma| PrimitiveStyle::with_fill(Gray4::new(luma));
+        Rectangle::new(Point::new(2, 1), Size::new(20, 6))
+            .into_styled(style(3))
+            .draw(target)
+            .unwrap();
+        Rectangle::new(Point::new(10, 5), Size::new(25, 9))
+            .into_styled(style(9))
+            .draw(target)
+            .unwrap();
+        MonoTextStyleBuilder::new()
+            .text_color(Gray4::new(6))
+            .font(&mono_font::ascii::FONT_6X10)
+            .build()
+            .draw_string("42", Point::new(24, 15), Baseline::Bottom, target)
+            .unwrap();
+    }
+
+    #[test]
+    fn flat_layout_renders_as_per_row_layout() {
+        let (width, height) = (40, 16);
+        let mut canvas = canvas(width, height).with_fixed_palette();
+        draw_sample(&mut canvas);
+        let white: u8 = Gray2Color::White.into();
+        let mut per_row = PerRowCanvas {
+            rows: vec![vec![white; height as usize]; width as usize],
+        };
+        draw_sample(&mut per_row);
+
+        let (gray, black_white) = per_row.render();
+        assert!(gray.iter().any(|&byte| byte != 0), "nothing drawn");
+        assert_eq!(canvas.render_gray(), gray);
+        assert_eq!(canvas.render_black_white(), black_white);
+    }
+
+    #[test]
+    fn draw_clips_pixels_off_each_edge() {
+        let mut canvas = canvas(8, 8);
+        let fill = PrimitiveStyle::with_fill(Gray4::BLACK);
+        // over the top left and the bottom right corners
+        Rectangle::new(Point::new(-4, -4), Size::new(8, 8))
+            .into_styled(fill)
+            .draw(&mut canvas)
+            .unwrap();
+        Rectangle::new(Point::new(6, 6), Size::new(8, 8))
+            .into_styled(fill)
+            .draw(&mut canvas)
+            .unwrap();
+
+        let black: u8 = Gray2Color::Black.into();
+        for point in canvas.bounding_box().points() {
+            let inside = (point.x < 4 && point.y < 4) || (point.x >= 6 && point.y >= 6);
+            assert_eq!(
+                canvas.get_pixel(point) == Some(black),
+                inside,
+                "{:?}",
+                point
+            );
+        }
+        assert_eq!(canvas.get_pixel(Point::new(-1, 0)), None);
+        assert_eq!(canvas.get_pixel(Point::new(0, 8)), None);
+    }
+
+    // a bar of 2 ram rows for each luma
+    fn draw_gray_bars(canvas: &mut Canvas, lumas: &[u8]) -> Result<(), Error> {
+        for (i, &luma) in lumas.iter().enumerate() {
+            let bar = Rectangle::new(Point::new(i as i32 * 2, 0), Size::new(2, 8));
+            bar.into_styled(PrimitiveStyle::with_fill(Gray4::new(luma)))
+                .draw(canvas)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn identity_curve_keeps_render() {
+        let identity = core::array::from_fn(|i| i as u8);
+        for lumas in [&[0u8, 5, 10, 15][..], &[3, 9], &[7]] {
+            let mut plain = canvas(8, 8);
+            let mut curved = canvas(8, 8).with_gray_curve(identity);
+            draw_gray_bars(&mut plain, lumas).unwrap();
+            draw_gray_bars(&mut curved, lumas).unwrap();
+            assert_eq!(curved.render_gray(), plain.render_gray(), "{:?}", lumas);
+        }
+
+        // a fifth gray is rejected, the default curve merges 0 and 1
+        let mut plain = canvas(10, 8);
+        assert!(draw_gray_bars(&mut plain, &[0, 1, 2, 8, 15]).is_err());
+        let mut curved = canvas(10, 8).with_gray_curve(DEFAULT_GRAY_CURVE);
+        draw_gray_bars(&mut curved, &[0, 1, 2, 8, 15]).unwrap();
+    }
+
+    #[test]
+    fn co2_level_by_thresholds() {
+        let thresholds = Co2Thresholds::default();
+        assert_eq!(thresholds.level(0), Co2Level::Good);
+        assert_eq!(thresholds.level(799), Co2Level::Good);
+        assert_eq!(thresholds.level(800), Co2Level::Fair);
+        assert_eq!(thresholds.level(1200), Co2Level::Fair);
+        assert_eq!(thresholds.level(1201), Co2Level::Poor);
+        assert_eq!(thresholds.level(u16::MAX).label(), "POOR");
+
+        let thresholds = Co2Thresholds {
+            fair: 600,
+            poor: 1000,
+        };
+        assert_eq!(thresholds.level(700).label(), "FAIR");
+        assert_eq!(thresholds.level(1100).label(), "POOR");
+    }
+
+    #[test]
+    fn co2_trend_of_readings() {
+        assert_eq!(Co2Trend::classify(821, 800, 20), Co2Trend::Rising);
+        assert_eq!(Co2Trend::classify(820, 800, 20), Co2Trend::Stable);
+        assert_eq!(Co2Trend::classify(780, 800, 20), Co2Trend::Stable);
+        assert_eq!(Co2Trend::classify(779, 800, 20), Co2Trend::Falling);
+        assert_eq!(Co2Trend::classify(10, 0, 20), Co2Trend::Stable);
+
+        let mut screen = Screen::new_with_history(&Size::new(296, 128), 10).with_trend(3, 20);
+        let mut trend_after = |co2_ppm: u16| {
+            screen.render(&MeasureResult {
+                co2_ppm,
+                ..Default::default()
+            });
+            screen.trend()
+        };
+        // no history to compare with
+        assert_eq!(trend_after(800), None);
+        assert_eq!(trend_after(810), Some(Co2Trend::Stable));
+        // average of 800 and 810 is 805
+        assert_eq!(trend_after(830), Some(Co2Trend::Rising));
+        // average of the last 3 is 813
+        assert_eq!(trend_after(820), Some(Co2Trend::Stable));
+        assert_eq!(trend_after(790), Some(Co2Trend::Falling));
+    }
+
+    #[test]
+    fn draw_at_clips_right_and_bottom_edges() {
+        let mut sub = canvas(4, 4);
+        sub.clear(Gray4::BLACK);
+        let mut canvas = canvas(8, 8);
+        // ram rows 6..10 and pixels 6..10, only 6 and 7 are on the canvas
+        canvas.draw_at(sub, Point::new(6, 6));
+
+        let black: u8 = Gray2Color::Black.into();
+        for x in 0..8 {
+            for y in 0..8 {
+                let inside = x >= 6 && y >= 6;
+                let pixel = canvas.pixels[canvas.index((x, y))];
+                assert_eq!(pixel == black, inside, "ram ({}, {})", x, y);
+            }
+        }
     }
 }
@@ -0,0 +1,50 @@
+use crate::e_paper::EspEPaper;
+use crate::error::Error;
+use crate::nvs;
+use crate::scd41::SCD41;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use log::{info, warn};
+
+/// reset the whole device to a known state
+///
+/// the sensor is stopped and reset to factory defaults, the panel is cleared
+/// to white with a full refresh, and the saved config is erased.
+/// all steps are run even if one fails, the failed ones are reported together.
+/// the device should be restarted afterwards so the default config is loaded
+pub fn factory_reset(scd: &mut SCD41, ep: &EspEPaper) -> Result<(), Error> {
+    let mut errors: Vec<String> = Vec::new();
+
+    info!("factory reset scd41");
+    // stop is accepted even if the sensor is idle
+    if let Err(err) = scd.stop().and_then(|_| scd.factory_reset()) {
+        warn!("factory reset scd41 error: {:?}", err);
+        errors.push(format!("scd41: {}", err));
+    }
+
+    info!("clear e-paper");
+    if let Err(err) = ep
+        .init_black_white()
+        .and_then(|_| ep.clear_screen())
+        .and_then(|_| ep.halt())
+    {
+        warn!("clear e-paper error: {:?}", err);
+        errors.push(format!("e-paper: {}", err));
+    }
+
+    info!("erase config");
+    if let Err(err) = nvs::Config::erase() {
+        warn!("erase config error: {:?}", err);
+        errors.push(format!("nvs: {}", err));
+    }
+
+    if !errors.is_empty() {
+        return Err(Error::SimpleError(format!(
+            "factory reset failed, {}",
+            errors.join(", ")
+        )));
+    }
+    info!("factory reset finish");
+    Ok(())
+}
@@ -1,42 +1,242 @@
-use crate::utils::DebugPrinter;
+use crate::canvas::Canvas;
+use crate::error::Error;
+use crate::utils::{available_bytes, DebugPrinter};
 use alloc::string::ToString;
-use alloc::vec;
 use alloc::vec::Vec;
-use core::cell::RefCell;
-use embedded_graphics::prelude::Size;
+use alloc::{format, vec};
+use core::cell::{Cell, RefCell};
+use embedded_graphics::pixelcolor::{Gray4, GrayColor};
+use embedded_graphics::prelude::{Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::spi::SpiBus;
+#[cfg(feature = "esp32")]
 use esp_hal::delay::Delay;
+#[cfg(feature = "esp32")]
 use esp_hal::gpio::{Input, Output};
-use esp_hal::spi::master::Spi;
-use esp_hal::spi::Error;
+#[cfg(feature = "esp32")]
+use esp_hal::spi::master::{Config as SpiConfig, Spi};
+#[cfg(feature = "esp32")]
+use esp_hal::time::Rate;
+#[cfg(feature = "esp32")]
 use esp_hal::Blocking;
-use log::debug;
+use log::{debug, warn};
+
+// data entry mode (0x11), x and y increase, the rotation is done by `Canvas`
+const DATA_ENTRY_MODE: u8 = 0x03;
+// max wait time for busy pin, full refresh takes a few seconds
+const DEFAULT_BUSY_TIMEOUT_MS: u32 = 10_000;
+// partial updates before `display` does a full refresh to clear the ghosting
+const DEFAULT_FULL_REFRESH_EVERY: u32 = 100;
+// heap kept free besides a frame, for fragmentation and the network stack
+const HEAP_MARGIN: usize = 4 * 1024;
+
+/// max spi clock of the panel, the serial write cycle of the ssd1680 is 50ns at least
+/// by its datasheet. not measured on the module, long wires may need less
+///
+/// esp-hal runs at 1MHz by default, one ram of 296x128 is 4736 bytes,
+/// so writing both rams takes about 76ms at 1MHz and 4ms at 20MHz.
+/// the refresh itself is bound by the busy pin and does not get faster
+pub const MAX_SPI_FREQUENCY_MHZ: u32 = 20;
+
+/// spi clock used by the firmware, half of `MAX_SPI_FREQUENCY_MHZ` as a margin
+/// until the max is verified on the panel, both rams take about 8ms
+pub const DEFAULT_SPI_FREQUENCY_MHZ: u32 = 10;
+
+/// rotation of the panel content, clockwise
+///
+/// all rotations are done by `Canvas`, the panel always scans the ram the same way.
+/// scanning backwards would reverse the bytes but not the 8 pixels in a byte,
+/// and move the padding bits of a short side like 122 to the start of the scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Rotation {
+    /// whether width and height are swapped
+    pub fn is_portrait(&self) -> bool {
+        matches!(self, Rotation::Rotate90 | Rotation::Rotate270)
+    }
+}
+
+/// what differs between the ssd168x panels this driver supports
+///
+/// the panels share the command set, these are the differences:
+/// - ram size, set by driver output control (0x01) and the ram window (0x44, 0x45)
+/// - source range, the second byte of display update control (0x21)
+/// - border waveform (0x3c)
+/// - luts (0x32) and voltages (0x3f, 0x03, 0x04, 0x2c), or the waveform in otp
+///   loaded by the display update sequence (0x22) when there is no custom lut
+///
+/// NOTE: the 7.5" panels are NOT supported. they use a uc8179 with another command set,
+/// e.g. panel setting (0x00), power on (0x04), data in 0x10 and 0x13, refresh (0x12)
+/// and a busy pin that is low while busy, so they need their own driver, not a descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PanelDescriptor {
+    pub name: &'static str,
+    /// gate lines, along the ram y-axis
+    pub width: u16,
+    /// source lines, along the ram x-axis, 8 pixels per byte
+    pub height: u16,
+    /// second byte of display update control (0x21)
+    pub source_output: u8,
+    /// border waveform (0x3c) of black white mode, none for the reset default
+    pub border_waveform: Option<u8>,
+    /// full refresh lut, the otp waveform is used if none
+    pub full_lut: Option<&'static [u8; 159]>,
+    pub fast_lut: Option<&'static [u8; 159]>,
+    /// partial update lut, the otp waveform is used if none
+    pub partial_lut: Option<&'static [u8; 159]>,
+    /// gray4 is not supported if none
+    pub gray4_lut: Option<&'static [u8; 159]>,
+    /// display update sequence (0x22) of a full refresh
+    pub full_update: u8,
+    /// display update sequence (0x22) of a partial update
+    pub partial_update: u8,
+}
+
+impl PanelDescriptor {
+    /// size for `Canvas` and `Screen`
+    pub fn size(&self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+}
+
+/// waveshare 2.9" v2, ssd1680, 296x128
+pub const EPD_2IN9_V2: PanelDescriptor = PanelDescriptor {
+    name: "2.9\" v2",
+    width: 296,
+    height: 128,
+    // source S8 to S167
+    source_output: 0x80,
+    border_waveform: None,
+    full_lut: Some(&LUT_2IN9_BLACK_WHITE),
+    fast_lut: Some(&LUT_2IN9_FAST),
+    partial_lut: Some(&LUT_2IN9_PARTIAL),
+    gray4_lut: Some(&LUT_2IN9_GRAY4),
+    // clock, analog, display, analog and clock off, with the loaded lut
+    full_update: 0xc7,
+    // display mode 2 with the loaded lut
+    partial_update: 0x0f,
+};
+
+/// waveshare 4.2" v2, ssd1683, 400x300
+///
+/// the 400 pixels are along the ram x-axis, so the size is 300x400 and
+/// the landscape layout needs `Rotation::Rotate90`. it refreshes with the otp waveforms
+pub const EPD_4IN2_V2: PanelDescriptor = PanelDescriptor {
+    name: "4.2\" v2",
+    width: 300,
+    height: 400,
+    // all sources
+    source_output: 0x00,
+    border_waveform: Some(0x05),
+    full_lut: None,
+    fast_lut: None,
+    partial_lut: None,
+    gray4_lut: None,
+    // load temperature and otp lut, then display
+    full_update: 0xf7,
+    // load temperature and otp lut, display mode 2
+    partial_update: 0xff,
+};
+
+/// e-paper driver with esp_hal peripherals
+#[cfg(feature = "esp32")]
+pub type EspEPaper<'a> =
+    EPaper<Spi<'a, Blocking>, Output<'a>, Output<'a>, Output<'a>, Input<'a>, Delay>;
+
+/// spi config for the panel, `frequency_mhz` is capped at `MAX_SPI_FREQUENCY_MHZ`
+#[cfg(feature = "esp32")]
+pub fn spi_config(frequency_mhz: u32) -> SpiConfig {
+    SpiConfig::default().with_frequency(Rate::from_mhz(spi_frequency(frequency_mhz)))
+}
+
+#[cfg(feature = "esp32")]
+fn spi_frequency(frequency_mhz: u32) -> u32 {
+    if frequency_mhz > MAX_SPI_FREQUENCY_MHZ {
+        warn!(
+            "spi frequency {}MHz exceeds panel max, use {}MHz",
+            frequency_mhz, MAX_SPI_FREQUENCY_MHZ
+        );
+        return MAX_SPI_FREQUENCY_MHZ;
+    }
+    frequency_mhz
+}
+
+#[cfg(feature = "esp32")]
+impl EspEPaper<'_> {
+    /// change the spi clock, capped at `MAX_SPI_FREQUENCY_MHZ`
+    pub fn set_spi_frequency(&self, frequency_mhz: u32) -> Result<(), Error> {
+        self.flush()?;
+        self.spi
+            .borrow_mut()
+            .apply_config(&spi_config(frequency_mhz))
+            .map_err(|err| Error::SimpleError(format!("apply spi config error: {:?}", err)))
+    }
+}
 
 // ref 1: https://www.waveshare.net/wiki/Pico-ePaper-2.9
 // ref 2: https://www.waveshare.net/w/upload/7/79/2.9inch-e-paper-v2-specification.pdf
-pub struct EPaper<'a> {
-    spi: RefCell<Spi<'a, Blocking>>,
+// ref 3: https://www.waveshare.net/wiki/4.2inch_e-Paper_Module
+pub struct EPaper<SPI, DC, RST, PWR, BUSY, D> {
+    spi: RefCell<SPI>,
     // 0 for cmd, 1 for data
-    dc: RefCell<Output<'a>>,
+    dc: RefCell<DC>,
     // 1 for reset
-    reset: RefCell<Output<'a>>,
-    power: RefCell<Output<'a>>,
+    reset: RefCell<RST>,
+    power: RefCell<PWR>,
     // 1 for busy
-    busy: RefCell<Input<'a>>,
+    busy: RefCell<BUSY>,
+    // false after power off, writes are rejected until init again
+    powered: Cell<bool>,
+    // last level set to dc, true for data, none before the first write
+    dc_data: Cell<Option<bool>>,
+    rotation: Cell<Rotation>,
+    // partial updates since the last full refresh
+    partial_count: Cell<u32>,
+    full_refresh_every: Cell<u32>,
+    // black white frame on the panel, written to the old frame ram before a partial update.
+    // none if unknown, e.g. after a gray4 or red refresh
+    last_frame: RefCell<Option<Vec<u8>>>,
+    // swap black and white by reading the rams inverted
+    inverted: Cell<bool>,
 
-    delay: Delay,
+    delay: RefCell<D>,
 
+    panel: PanelDescriptor,
     width: u16,
     height: u16,
 }
 
-impl<'a> EPaper<'a> {
+impl<SPI, DC, RST, PWR, BUSY, D> EPaper<SPI, DC, RST, PWR, BUSY, D>
+where
+    SPI: SpiBus,
+    DC: OutputPin,
+    RST: OutputPin,
+    PWR: OutputPin,
+    BUSY: InputPin,
+    D: DelayNs,
+    Error: From<SPI::Error>
+        + From<DC::Error>
+        + From<RST::Error>
+        + From<PWR::Error>
+        + From<BUSY::Error>,
+{
     pub fn new(
-        size: &Size,
-        spi: Spi<'a, Blocking>,
-        power: Output<'a>,
-        busy: Input<'a>,
-        reset: Output<'a>,
-        dc: Output<'a>,
+        panel: &PanelDescriptor,
+        spi: SPI,
+        power: PWR,
+        busy: BUSY,
+        reset: RST,
+        dc: DC,
+        delay: D,
     ) -> Self {
         EPaper {
             spi: RefCell::new(spi),
@@ -44,102 +244,237 @@ impl<'a> EPaper<'a> {
             reset: RefCell::new(reset),
             power: RefCell::new(power),
             busy: RefCell::new(busy),
+            powered: Cell::new(false),
+            dc_data: Cell::new(None),
+            rotation: Cell::new(Rotation::default()),
+            partial_count: Cell::new(0),
+            full_refresh_every: Cell::new(DEFAULT_FULL_REFRESH_EVERY),
+            last_frame: RefCell::new(None),
+            inverted: Cell::new(false),
+
+            delay: RefCell::new(delay),
+            panel: *panel,
+            width: panel.width,
+            height: panel.height,
+        }
+    }
+
+    pub fn panel(&self) -> &PanelDescriptor {
+        &self.panel
+    }
+
+    /// set rotation of the canvas drawn for the panel, e.g. by `display_message`.
+    /// the ram layout does not change, the rotation is done by `Canvas`
+    pub fn set_rotation(&self, rotation: Rotation) {
+        self.rotation.set(rotation);
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        self.rotation.get()
+    }
+
+    /// swap black and white on the panel without changing the data, takes effect
+    /// from the next init, e.g. the next `display`
+    ///
+    /// both rams are read inverted, so a gray4 level becomes `3 - level`, which is
+    /// the inverse with the gray4 lut. the partial update keeps working since the
+    /// old frame is inverted as well. on a three-color panel only black white is inverted
+    pub fn set_inverted(&self, inverted: bool) {
+        self.inverted.set(inverted);
+    }
 
-            delay: Delay::new(),
-            width: size.width as u16,
-            height: size.height as u16,
+    pub fn is_inverted(&self) -> bool {
+        self.inverted.get()
+    }
+
+    /// full refresh in `display` after `count` partial updates, 0 to only do it when forced
+    pub fn set_full_refresh_every(&self, count: u32) {
+        self.full_refresh_every.set(count);
+    }
+
+    /// partial update black white data, or full refresh if forced or
+    /// there have been too many partial updates, see `set_full_refresh_every`
+    pub fn display(&self, data: &[u8], force_full: bool) -> Result<(), Error> {
+        let every = self.full_refresh_every.get();
+        let full = force_full || (every > 0 && self.partial_count.get() >= every);
+        if full {
+            debug!(
+                "full refresh after {} partial updates",
+                self.partial_count.get()
+            );
+            self.init_black_white()?;
+            self.display_black_white(data)
+        } else {
+            self.display_partial(data)
+        }
+    }
+
+    /// full refresh with a few lines of text, e.g. for boot or error messages
+    ///
+    /// lines out of the panel are clipped. the canvas is dropped before the refresh,
+    /// so only the frame is kept in memory while waiting for the panel
+    pub fn display_message(&self, lines: &[&str]) -> Result<(), Error> {
+        DebugPrinter::new("display message".to_string());
+        const LINE_HEIGHT: i32 = 24;
+        let data = {
+            let size = Size::new(self.width as u32, self.height as u32);
+            let mut canvas = Canvas::with_rotation(&size, self.rotation.get()).with_fixed_palette();
+            canvas.clear(Gray4::WHITE);
+            for (idx, line) in lines.iter().enumerate() {
+                canvas.draw_text(line, Point::new(10, LINE_HEIGHT * (idx as i32 + 1)))?;
+            }
+            canvas.render_black_white()
+        };
+        self.init_black_white()?;
+        self.display_black_white(data.as_slice())
+    }
+
+    /// full refresh `canvas` in gray4, or in black white if the heap is too low
+    /// for the gray4 buffers, the frame is skipped if even that does not fit
+    ///
+    /// gray4 needs 4 frames of memory at once, the rendered data and its 2 split frames
+    pub fn display_canvas_gray4(&self, canvas: &Canvas) -> Result<(), Error> {
+        DebugPrinter::new("display canvas gray4".to_string());
+        let available = available_bytes();
+        if available >= self.frame_len() * 4 + HEAP_MARGIN {
+            let data = canvas.render_gray();
+            self.init_gray4()?;
+            return self.display_gray4(data.as_slice());
+        }
+        if available < self.frame_len() + HEAP_MARGIN {
+            warn!("only {} bytes free, skip frame", available);
+            return Err(Error::SimpleError(format!(
+                "not enough memory for a frame, {} bytes free",
+                available
+            )));
         }
+        warn!(
+            "only {} bytes free, fall back to black white instead of gray4",
+            available
+        );
+        let data = canvas.render_black_white();
+        self.init_black_white()?;
+        self.display_black_white(data.as_slice())
     }
 
     pub fn init_black_white(&self) -> Result<(), Error> {
         DebugPrinter::new("init black white".to_string());
-        self.power_up();
-        self.hw_reset();
-        self.wait_busy();
+        match self.panel.full_lut {
+            Some(lut) => self.init_with_lut(lut)?,
+            None => self.init_controller()?,
+        }
+        // TODO: this is not necessary on init, we should call it manually
+        self.clear_screen()?;
+        self.wait_busy()?;
+        // self.delay_ms(1000);
+        Ok(())
+    }
+
+    /// init black white mode with a custom lut, e.g. `fast_lut` to trade quality for speed
+    pub fn init_with_lut(&self, lut: &[u8; 159]) -> Result<(), Error> {
+        DebugPrinter::new("init with lut".to_string());
+        self.init_controller()?;
+        // self.delay_ms(1000);
+        self.init_lut(*lut)
+    }
+
+    /// reset the controller and set up black white mode, the lut is left as is
+    fn init_controller(&self) -> Result<(), Error> {
+        self.power_up()?;
+        self.hw_reset()?;
+        self.wait_busy()?;
 
         // soft reset
         self.write_cmd(0x12)?;
-        self.wait_busy();
+        self.wait_busy()?;
 
-        let h1 = (self.height / 8 - 1) as u8;
         let w1 = ((self.width - 1) % 256) as u8;
         let w2 = ((self.width - 1) / 256) as u8;
         let init_seq = vec![
             // driver output control
             (0x01, vec![w1, w2, 0x00]),
             // data entry mode
-            (0x11, vec![0x03]),
-            // screen resolution, set x
-            (0x44, vec![0x00, h1]),
-            // screen resolution, set y
-            (0x45, vec![0x00, 0x00, w1, w2]),
+            (0x11, vec![DATA_ENTRY_MODE]),
             // display update control
-            (0x21, vec![0x00, 0x80]),
-            // cursor x
-            (0x4e, vec![0x00]),
-            // cursor y
-            (0x4f, vec![0x00, 0x00]),
+            (0x21, vec![self.ram_option(), self.panel.source_output]),
         ];
         for (cmd, data) in init_seq {
             self.write_cmd(cmd)?;
             self.write_data(data.as_slice())?;
         }
-        self.wait_busy();
+        if let Some(border) = self.panel.border_waveform {
+            self.write_cmd(0x3c)?;
+            self.write_data(&[border])?;
+        }
+        // screen resolution and cursor
+        self.set_ram_window(0, self.ram_x_end(), 0, self.width - 1)?;
+        self.wait_busy()
+    }
 
-        let lut = self.black_white_lut();
-        // self.delay.delay_millis(1000);
-        self.init_lut(lut)?;
-        // TODO: this is not necessary on init, we should call it manually
-        self.clear_screen()?;
-        self.wait_busy();
-        // self.delay.delay_millis(1000);
-        Ok(())
+    /// load the temperature compensated lut from otp with the internal temperature sensor,
+    /// it replaces the custom lut until next init, useful to compare with the custom luts
+    ///
+    /// NOTE: the temperature (0x1b) and the selected lut are not read back,
+    /// the module only wires din and the driver never reads from the panel
+    pub fn load_otp_lut(&self) -> Result<(), Error> {
+        DebugPrinter::new("load otp lut".to_string());
+        let init_seq = vec![
+            // temperature sensor control, internal sensor
+            (0x18, vec![0x80]),
+            // load temperature and lut from otp
+            (0x22, vec![0xb1]),
+            (0x20, vec![]),
+        ];
+        for (cmd, data) in init_seq {
+            self.write_cmd(cmd)?;
+            self.write_data(data.as_slice())?;
+        }
+        self.wait_busy()
     }
 
     pub fn init_gray4(&self) -> Result<(), Error> {
         DebugPrinter::new("init gray4".to_string());
-        self.power_up();
-        self.hw_reset();
-        self.wait_busy();
+        let Some(lut) = self.panel.gray4_lut else {
+            return Err(Error::SimpleError(format!(
+                "gray4 not supported by {}",
+                self.panel.name
+            )));
+        };
+        self.power_up()?;
+        self.hw_reset()?;
+        self.wait_busy()?;
 
         // soft reset
         self.write_cmd(0x12)?;
-        self.wait_busy();
+        self.wait_busy()?;
 
-        let h1 = (self.height / 8) as u8;
         let w1 = ((self.width - 1) % 256) as u8;
         let w2 = ((self.width - 1) / 256) as u8;
         let init_seq = vec![
             // driver output control
             (0x01, vec![w1, w2, 0x00]),
             // data entry mode
-            (0x11, vec![0x03]),
-            // TODO: dynamic resolution
-            // screen resolution, x: 128
-            // x start, x end, divided by 8
-            (0x44, vec![0x01, h1]),
-            // screen resolution, y: 296
-            // y start, y end, divided by 256
-            (0x45, vec![0x00, 0x00, w1, w2]),
-            // display update control
+            (0x11, vec![DATA_ENTRY_MODE]),
+            // border waveform
             (0x3c, vec![0x04]),
-            // cursor x
-            (0x4e, vec![0x01]),
-            // cursor y
-            (0x4f, vec![0x00, 0x00]),
+            // display update control
+            (0x21, vec![self.ram_option(), 0x00]),
         ];
         for (cmd, data) in init_seq {
             self.write_cmd(cmd)?;
             self.write_data(data.as_slice())?;
         }
-        self.wait_busy();
+        // screen resolution and cursor
+        // NOTE: the vendor code starts x from 0x01 and ends at height/8,
+        //   which is one byte off from black white mode, so use the same window here
+        self.set_ram_window(0, self.ram_x_end(), 0, self.width - 1)?;
+        self.wait_busy()?;
 
-        let lut = self.gray4_lut();
-        self.delay.delay_millis(1000);
-        self.init_lut(lut)?;
+        self.delay_ms(1000);
+        self.init_lut(*lut)?;
         // TODO: this is not necessary on init, we should call it manually
         self.clear_screen()?;
-        self.delay.delay_millis(1000);
+        self.delay_ms(1000);
         Ok(())
     }
 
@@ -147,66 +482,123 @@ impl<'a> EPaper<'a> {
         DebugPrinter::new("display partial".to_string());
         self.init_partial_update()?;
 
+        // the waveform depends on the old frame, which is stale after the last update
+        if let Some(last) = self.last_frame.borrow().as_ref() {
+            self.write_cmd(0x26)?;
+            self.write_data(last.as_slice())?;
+        }
         // write data to black-white cache
         self.write_cmd(0x24)?;
         self.write_data(data)?;
 
         self.sync_partial_screen()?;
+        self.partial_count.set(self.partial_count.get() + 1);
+        self.last_frame.replace(Some(data.to_vec()));
+        Ok(())
+    }
+
+    /// partial update only the given region of the panel
+    ///
+    /// region is in panel ram coordinates: x runs along the short side (`height`),
+    /// y runs along the long side (`width`). each byte holds 8 pixels on the x-axis,
+    /// so x and width of the region must be aligned to 8 pixels.
+    /// data holds `region.size.height` rows of `region.size.width / 8` bytes
+    pub fn display_partial_region(&self, data: &[u8], region: Rectangle) -> Result<(), Error> {
+        DebugPrinter::new("display partial region".to_string());
+        let x = region.top_left.x;
+        let y = region.top_left.y;
+        let w = region.size.width as i32;
+        let h = region.size.height as i32;
+        if x < 0 || y < 0 || w == 0 || h == 0 || x % 8 != 0 || w % 8 != 0 {
+            return Err(Error::SimpleError(format!(
+                "region {:?} is not aligned to 8 pixels on x-axis",
+                region
+            )));
+        }
+        if x + w > self.height as i32 || y + h > self.width as i32 {
+            return Err(Error::SimpleError(format!(
+                "region {:?} out of screen",
+                region
+            )));
+        }
+        let required_len = (w / 8 * h) as usize;
+        if data.len() != required_len {
+            return Err(Error::SimpleError(format!(
+                "region data len {} not eq {}",
+                data.len(),
+                required_len
+            )));
+        }
+        self.init_partial_update()?;
+
+        let x1 = (x / 8) as u8;
+        let x2 = ((x + w) / 8 - 1) as u8;
+        self.set_ram_window(x1, x2, y as u16, (y + h - 1) as u16)?;
+
+        // old frame of the region, the rest of the last frame is kept
+        let row_len = self.ram_x_end() as usize + 1;
+        let region_row_len = (w / 8) as usize;
+        let mut last_frame = self.last_frame.borrow_mut();
+        if let Some(last) = last_frame.as_mut() {
+            let old: Vec<u8> = (0..h as usize)
+                .flat_map(|row| {
+                    let start = (y as usize + row) * row_len + x1 as usize;
+                    last[start..start + region_row_len].iter().copied()
+                })
+                .collect();
+            self.write_cmd(0x26)?;
+            self.write_data(old.as_slice())?;
+            for (row, new) in data.chunks(region_row_len).enumerate() {
+                let start = (y as usize + row) * row_len + x1 as usize;
+                last[start..start + region_row_len].copy_from_slice(new);
+            }
+        }
+        drop(last_frame);
+        // write data to black-white cache
+        self.write_cmd(0x24)?;
+        self.write_data(data)?;
+
+        self.sync_partial_screen()?;
+        self.partial_count.set(self.partial_count.get() + 1);
         Ok(())
     }
 
     pub fn init_partial_update(&self) -> Result<(), Error> {
         DebugPrinter::new("init partial update".to_string());
-        self.power_up();
-        self.hw_reset();
-        self.wait_busy();
+        self.power_up()?;
+        self.hw_reset()?;
+        self.wait_busy()?;
 
-        let lut = self.partial_update_lut();
-        self.write_cmd(0x32)?;
-        self.write_data(lut[0..153].iter().as_slice())?;
-
-        let init_seq = vec![
-            (
-                0x37,
-                vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00],
-            ),
-            (0x3c, vec![0x80]),
-            (0x22, vec![0xc0]),
-            (0x20, vec![]),
-        ];
-        for (cmd, data) in init_seq {
-            self.write_cmd(cmd)?;
-            self.write_data(data.as_slice())?;
+        // the otp waveform is loaded by the update sequence if there is no lut
+        if let Some(lut) = self.panel.partial_lut {
+            self.write_cmd(0x32)?;
+            self.write_data(lut[0..153].iter().as_slice())?;
+            // write display option, display mode 2
+            self.write_cmd(0x37)?;
+            self.write_data(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00])?;
         }
-        self.wait_busy();
-        let h1 = (self.height / 8 - 1) as u8;
-        let w1 = ((self.width - 1) % 256) as u8;
-        let w2 = ((self.width - 1) / 256) as u8;
-        let init_seq = vec![
-            // screen resolution, x
-            (0x44, vec![0x00, h1]),
-            // screen resolution, y
-            (0x45, vec![0x00, 0x00, w1, w2]),
-            // display update control
-            // (0x3c, vec![0x04]),
-            // cursor x: 0
-            (0x4e, vec![0x00]),
-            // cursor y: 0
-            (0x4f, vec![0x00, 0x00]),
-        ];
+        let init_seq = vec![(0x3c, vec![0x80]), (0x22, vec![0xc0]), (0x20, vec![])];
         for (cmd, data) in init_seq {
             self.write_cmd(cmd)?;
             self.write_data(data.as_slice())?;
         }
-        self.wait_busy();
+        self.wait_busy()?;
+        // data entry mode is reset by hw reset
+        self.write_cmd(0x11)?;
+        self.write_data(&[DATA_ENTRY_MODE])?;
+        // display update control
+        self.write_cmd(0x21)?;
+        self.write_data(&[self.ram_option(), 0x00])?;
+        // (0x3c, vec![0x04]),
+        self.set_ram_window(0, self.ram_x_end(), 0, self.width - 1)?;
+        self.wait_busy()?;
         Ok(())
     }
 
     // clear screen, aka set all pixel to white
     pub fn clear_screen(&self) -> Result<(), Error> {
         DebugPrinter::new("clear screen".to_string());
-        let len = self.width / 8 * self.height;
-        let data = vec![0xff; len as usize];
+        let data = vec![0xff; self.frame_len()];
         // write data to black-white cache
         self.write_cmd(0x24)?;
         self.write_data(data.as_slice())?;
@@ -214,24 +606,58 @@ impl<'a> EPaper<'a> {
         self.write_cmd(0x26)?;
         self.write_data(data.as_slice())?;
         self.sync_screen()?;
+        self.last_frame.replace(Some(data));
+        Ok(())
+    }
+
+    /// set all pixels to black with a full refresh
+    pub fn clear_to_black(&self) -> Result<(), Error> {
+        DebugPrinter::new("clear to black".to_string());
+        let data = vec![0x00; self.frame_len()];
+        self.write_cmd(0x24)?;
+        self.write_data(data.as_slice())?;
+        self.write_cmd(0x26)?;
+        self.write_data(data.as_slice())?;
+        self.sync_screen()?;
+        self.last_frame.replace(Some(data));
+        Ok(())
+    }
+
+    /// clear the ghosting with `cycles` full refreshes to black and then white,
+    /// the panel is white afterwards
+    ///
+    /// this is slow, a cycle is 2 full refreshes, about 4s on the 2.9".
+    /// run it when the device is idle, e.g. at boot or right after a metric is sent,
+    /// so it does not delay a send
+    pub fn deep_clean(&self, cycles: usize) -> Result<(), Error> {
+        DebugPrinter::new("deep clean".to_string());
+        if cycles == 0 {
+            return Ok(());
+        }
+        self.init_black_white()?;
+        for _ in 0..cycles {
+            self.clear_to_black()?;
+            self.clear_screen()?;
+        }
+        self.partial_count.set(0);
         Ok(())
     }
 
     pub fn sync_screen(&self) -> Result<(), Error> {
         DebugPrinter::new("sync screen".to_string());
         self.write_cmd(0x22)?;
-        self.write_data(0xc7u8.to_be_bytes().as_ref())?;
+        self.write_data(&[self.panel.full_update])?;
         self.write_cmd(0x20)?;
-        self.wait_busy();
+        self.wait_busy()?;
         Ok(())
     }
 
     pub fn sync_partial_screen(&self) -> Result<(), Error> {
         DebugPrinter::new("sync partial screen".to_string());
         self.write_cmd(0x22)?;
-        self.write_data(0x0fu8.to_be_bytes().as_ref())?;
+        self.write_data(&[self.panel.partial_update])?;
         self.write_cmd(0x20)?;
-        self.wait_busy();
+        self.wait_busy()?;
         Ok(())
     }
 
@@ -243,10 +669,61 @@ impl<'a> EPaper<'a> {
         self.write_cmd(0x26)?;
         self.write_data(data)?;
         self.sync_screen()?;
+        self.partial_count.set(0);
+        self.last_frame.replace(Some(data.to_vec()));
+        Ok(())
+    }
+
+    /// display black white and red on a three-color panel
+    ///
+    /// `bw` and `red` are both height/8*width bytes, height rounded up to whole bytes.
+    /// bit 0 is black in `bw`, bit 1 is red in `red`, red covers black-white.
+    /// this refresh loads the waveform from panel otp, because our luts don't drive red
+    pub fn display_black_white_red(&self, bw: &[u8], red: &[u8]) -> Result<(), Error> {
+        DebugPrinter::new("display black white red".to_string());
+        let required_len = self.frame_len();
+        for (name, data) in [("black white", bw), ("red", red)] {
+            if data.len() != required_len {
+                return Err(Error::SimpleError(format!(
+                    "{} data len {} not eq {}",
+                    name,
+                    data.len(),
+                    required_len
+                )));
+            }
+        }
+        // display update control, red ram normal
+        let bw_option = if self.inverted.get() { 0x08 } else { 0x00 };
+        self.write_cmd(0x21)?;
+        self.write_data(&[bw_option, self.panel.source_output])?;
+        // write data to black-white cache
+        self.write_cmd(0x24)?;
+        self.write_data(bw)?;
+        // write data to red cache
+        self.write_cmd(0x26)?;
+        self.write_data(red)?;
+        // load temperature and otp lut, then display
+        self.write_cmd(0x22)?;
+        self.write_data(&[0xf7])?;
+        self.write_cmd(0x20)?;
+        self.wait_busy()?;
+        self.last_frame.replace(None);
         Ok(())
     }
 
+    /// full refresh 2 bit gray data, e.g. from `Canvas::render_gray`
+    ///
+    /// data holds `width` rows of ceil(height/8)*2 bytes, each row is padded to whole bytes
+    /// of 8 pixels since the ram is addressed by byte
     pub fn display_gray4(&self, data: &[u8]) -> Result<(), Error> {
+        let required_len = self.frame_len() * 2;
+        if data.len() != required_len {
+            return Err(Error::SimpleError(format!(
+                "gray4 data len {} not eq {}",
+                data.len(),
+                required_len
+            )));
+        }
         let (data1, data2) = Self::parse_gray4_data(data);
         // write data to black-white cache
         self.write_cmd(0x24)?;
@@ -255,6 +732,7 @@ impl<'a> EPaper<'a> {
         self.write_cmd(0x26)?;
         self.write_data(data2.as_slice())?;
         self.sync_screen()?;
+        self.last_frame.replace(None);
         Ok(())
     }
 
@@ -262,64 +740,191 @@ impl<'a> EPaper<'a> {
         self.write_cmd(0x10)?;
         self.write_data(0x01u8.to_be_bytes().as_ref())?;
         // self.shutdown();
+        self.flush()
+    }
+
+    /// enter deep sleep and cut the power of the panel,
+    /// call `init_black_white` or `init_gray4` again to wake it up
+    pub fn power_off(&self) -> Result<(), Error> {
+        DebugPrinter::new("power off".to_string());
+        // NOTE: busy keeps high in deep sleep, so wait for the last refresh before entering it
+        self.wait_busy()?;
+        self.halt()?;
+        self.delay_ms(10);
+        self.shutdown()
+    }
+
+    /// wait until the panel is not busy, return `Error::Timeout` if it takes longer than `max_ms`
+    pub fn wait_busy_timeout(&self, max_ms: u32) -> Result<(), Error> {
+        DebugPrinter::new("wait_busy".to_string());
+        const POLL_INTERVAL_MS: u32 = 50;
+        // busy is only raised after the last command is received
+        self.flush()?;
+        let mut waited = 0;
+        loop {
+            if self.busy.borrow_mut().is_low()? {
+                return Ok(());
+            }
+            if waited >= max_ms {
+                warn!("wait e-paper busy timeout after {}ms", waited);
+                return Err(Error::Timeout);
+            }
+            self.delay_ms(POLL_INTERVAL_MS);
+            waited += POLL_INTERVAL_MS;
+        }
+    }
+
+    /// send a raw command with its data, for commands not covered by this driver
+    pub fn send(&self, cmd: u8, data: &[u8]) -> Result<(), Error> {
+        self.write_cmd(cmd)?;
+        self.write_data(data)
+    }
+}
+
+// private functions
+impl<SPI, DC, RST, PWR, BUSY, D> EPaper<SPI, DC, RST, PWR, BUSY, D>
+where
+    SPI: SpiBus,
+    DC: OutputPin,
+    RST: OutputPin,
+    PWR: OutputPin,
+    BUSY: InputPin,
+    D: DelayNs,
+    Error: From<SPI::Error>
+        + From<DC::Error>
+        + From<RST::Error>
+        + From<PWR::Error>
+        + From<BUSY::Error>,
+{
+    fn set_cmd_flag(&self) -> Result<(), Error> {
+        self.set_dc(false)
+    }
+
+    fn set_data_flag(&self) -> Result<(), Error> {
+        self.set_dc(true)
+    }
+
+    /// change dc only if needed, pending transfer is finished before dc is changed
+    ///
+    /// writes are not flushed one by one, so a command and its data
+    /// only wait for the spi twice, when switching to data and back to cmd
+    fn set_dc(&self, data: bool) -> Result<(), Error> {
+        if self.dc_data.get() == Some(data) {
+            return Ok(());
+        }
+        self.flush()?;
+        if data {
+            self.dc.borrow_mut().set_high()?;
+        } else {
+            self.dc.borrow_mut().set_low()?;
+        }
+        self.dc_data.set(Some(data));
+        Ok(())
+    }
+
+    /// wait for pending spi transfer
+    fn flush(&self) -> Result<(), Error> {
+        self.spi.borrow_mut().flush()?;
         Ok(())
     }
 
-    // TODO: make private
-    pub fn write_cmd(&self, cmd: u8) -> Result<(), Error> {
-        self.set_cmd_flag();
+    fn write_cmd(&self, cmd: u8) -> Result<(), Error> {
+        if !self.powered.get() {
+            return Err(Error::PoweredOff);
+        }
+        self.set_cmd_flag()?;
         debug!("# 0x{:x}", cmd);
-        self.spi.borrow_mut().write(cmd.to_be_bytes().as_ref())
+        self.spi.borrow_mut().write(cmd.to_be_bytes().as_ref())?;
+        Ok(())
     }
 
-    pub fn write_data(&self, data: &[u8]) -> Result<(), Error> {
+    fn write_data(&self, data: &[u8]) -> Result<(), Error> {
         if data.is_empty() {
             return Ok(());
         }
-        self.set_data_flag();
+        if !self.powered.get() {
+            return Err(Error::PoweredOff);
+        }
+        self.set_data_flag()?;
         // debug!("{:?}", data);
-        self.spi.borrow_mut().write(data)
+        self.spi.borrow_mut().write(data)?;
+        Ok(())
     }
-}
 
-// private functions
-impl<'a> EPaper<'a> {
-    fn set_cmd_flag(&self) {
-        self.dc.borrow_mut().set_low()
+    /// last byte of the ram x-axis, the short side is rounded up to whole bytes
+    fn ram_x_end(&self) -> u8 {
+        (self.height.div_ceil(8) - 1) as u8
+    }
+
+    /// ram content option of display update control, red ram in the high nibble
+    fn ram_option(&self) -> u8 {
+        if self.inverted.get() {
+            0x88
+        } else {
+            0x00
+        }
+    }
+
+    /// bytes of a black white frame
+    fn frame_len(&self) -> usize {
+        self.height.div_ceil(8) as usize * self.width as usize
+    }
+
+    /// set ram window and move cursor to its start
+    ///
+    /// x is in bytes, y is in pixels, both inclusive
+    fn set_ram_window(&self, x1: u8, x2: u8, y1: u16, y2: u16) -> Result<(), Error> {
+        let [y_start1, y_start2] = y1.to_le_bytes();
+        let [y_end1, y_end2] = y2.to_le_bytes();
+        let init_seq = vec![
+            // window x
+            (0x44, vec![x1, x2]),
+            // window y
+            (0x45, vec![y_start1, y_start2, y_end1, y_end2]),
+            // cursor x
+            (0x4e, vec![x1]),
+            // cursor y
+            (0x4f, vec![y_start1, y_start2]),
+        ];
+        for (cmd, data) in init_seq {
+            self.write_cmd(cmd)?;
+            self.write_data(data.as_slice())?;
+        }
+        Ok(())
     }
 
-    fn set_data_flag(&self) {
-        self.dc.borrow_mut().set_high()
+    fn delay_ms(&self, ms: u32) {
+        self.delay.borrow_mut().delay_ms(ms);
     }
 
-    fn power_up(&self) {
-        self.power.borrow_mut().set_high();
-        self.delay.delay_millis(100);
+    fn power_up(&self) -> Result<(), Error> {
+        self.power.borrow_mut().set_high()?;
+        self.powered.set(true);
+        self.dc_data.set(None);
+        self.delay_ms(100);
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    fn shutdown(&self) {
-        self.power.borrow_mut().set_low();
+    fn shutdown(&self) -> Result<(), Error> {
+        self.flush()?;
+        self.power.borrow_mut().set_low()?;
+        self.powered.set(false);
+        Ok(())
     }
 
-    fn hw_reset(&self) {
+    fn hw_reset(&self) -> Result<(), Error> {
         DebugPrinter::new("hw_reset".to_string());
-        self.reset.borrow_mut().set_high();
-        self.delay.delay_millis(10);
-        self.reset.borrow_mut().set_low();
-        self.delay.delay_millis(2);
-        self.reset.borrow_mut().set_high();
-        self.delay.delay_millis(100);
+        self.reset.borrow_mut().set_high()?;
+        self.delay_ms(10);
+        self.reset.borrow_mut().set_low()?;
+        self.delay_ms(2);
+        self.reset.borrow_mut().set_high()?;
+        self.delay_ms(100);
+        Ok(())
     }
 
-    fn wait_busy(&self) {
-        DebugPrinter::new("wait_busy".to_string());
-        loop {
-            if self.busy.borrow_mut().is_low() {
-                break;
-            }
-            self.delay.delay_millis(50);
-        }
+    fn wait_busy(&self) -> Result<(), Error> {
+        self.wait_busy_timeout(DEFAULT_BUSY_TIMEOUT_MS)
     }
 
     fn init_lut(&self, lut: [u8; 159]) -> Result<(), Error> {
@@ -327,7 +932,7 @@ impl<'a> EPaper<'a> {
         // lut
         self.write_cmd(0x32)?;
         self.write_data(lut[0..153].iter().as_slice())?;
-        self.wait_busy();
+        self.wait_busy()?;
         self.write_cmd(0x3f)?;
         self.write_data(lut[153].to_be_bytes().as_slice())?;
         // gate voltage
@@ -342,123 +947,535 @@ impl<'a> EPaper<'a> {
         Ok(())
     }
 
+    /// split 2 bit pixels into the high bits (0x24) and the low bits (0x26) frame
+    ///
+    /// 2 bytes of gray make 1 byte of each frame, a trailing odd byte is padded with white
     fn parse_gray4_data(data: &[u8]) -> (Vec<u8>, Vec<u8>) {
         DebugPrinter::new("parse gray".to_string());
-        let mut data1 = Vec::new();
-        let mut data2 = Vec::new();
-        let mut index = 0;
-        data.chunks_exact(2).for_each(|chunk| {
-            let b1 = 0b10000000;
-            let b2 = 0b01000000;
-            let b3 = 0b00100000;
-            let b4 = 0b00010000;
-            let b5 = 0b00001000;
-            let b6 = 0b00000100;
-            let b7 = 0b00000010;
-            let b8 = 0b00000001;
-
-            let a = chunk[0];
-            let b = chunk[1];
-            let d1 = 0
-                + (a & b1)
-                + ((a & b3) << 1)
-                + ((a & b5) << 2)
-                + ((a & b7) << 3)
-                + ((b & b1) >> 4)
-                + ((b & b3) >> 3)
-                + ((b & b5) >> 2)
-                + ((b & b7) >> 1);
-            data1.push(d1);
-            let d2 = 0
-                + ((a & b2) << 1)
-                + ((a & b4) << 2)
-                + ((a & b6) << 3)
-                + ((a & b8) << 4)
-                + ((b & b2) >> 3)
-                + ((b & b4) >> 2)
-                + ((b & b6) >> 1)
-                + (b & b8);
-            data2.push(d2);
-            index += 1;
-        });
+        let mut data1 = Vec::with_capacity(data.len().div_ceil(2));
+        let mut data2 = Vec::with_capacity(data.len().div_ceil(2));
+        for chunk in data.chunks(2) {
+            let a = GRAY4_SPLIT[chunk[0] as usize];
+            // 0 is white
+            let b = chunk.get(1).map_or(0, |&b| GRAY4_SPLIT[b as usize]);
+            data1.push((a & 0xf0) | (b >> 4));
+            data2.push((a << 4) | (b & 0x0f));
+        }
         debug!("{}: {}, {}", data.len(), data1.len(), data2.len());
-        // data1.reverse();
-        // data2.reverse();
         (data1, data2)
     }
 }
 
+// for every byte of 4 pixels, the high bits (7, 5, 3, 1) in the high nibble
+// and the low bits (6, 4, 2, 0) in the low nibble
+const GRAY4_SPLIT: [u8; 256] = {
+    let mut table = [0u8; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut split = 0u8;
+        let mut pixel = 0;
+        while pixel < 4 {
+            let shift = 6 - pixel * 2;
+            let high = (byte >> (shift + 1)) & 1;
+            let low = (byte >> shift) & 1;
+            split |= ((high << (7 - pixel)) | (low << (3 - pixel))) as u8;
+            pixel += 1;
+        }
+        table[byte] = split;
+        byte += 1;
+    }
+    table
+};
+
 // init lookup tables
-impl<'a> EPaper<'a> {
-    fn black_white_lut(&self) -> [u8; 159] {
-        [
-            0x80, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, // VS L0
-            0x10, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, // VS L1
-            0x80, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, // VS L2
-            0x10, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, // VS L3
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L4
-            0x14, 0x08, 0x00, 0x00, 0x00, 0x00, 0x01, // TP, SR, RP of Group0
-            0x0A, 0x0A, 0x00, 0x0A, 0x0A, 0x00, 0x01, // TP, SR, RP of Group1
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group2
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group3
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group4
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group5
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group6
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group7
-            0x14, 0x08, 0x00, 0x01, 0x00, 0x00, 0x01, // TP, SR, RP of Group8
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // TP, SR, RP of Group9
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group10
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group11
-            0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x00, 0x00, 0x00, //FR, XON
-            0x22, 0x17, 0x41, 0x00, 0x32, 0x36, // EOPT VGH VSH1 VSH2 VSL VCOM
-        ]
+impl<SPI, DC, RST, PWR, BUSY, D> EPaper<SPI, DC, RST, PWR, BUSY, D> {
+    /// default full refresh lut, none if the panel uses the otp waveform
+    pub fn black_white_lut(&self) -> Option<&'static [u8; 159]> {
+        self.panel.full_lut
     }
 
-    fn gray4_lut(&self) -> [u8; 159] {
-        [
-            0x00, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L0
-            0x20, 0x60, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L1
-            0x28, 0x60, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L2
-            0x2A, 0x60, 0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L3
-            0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L4
-            0x00, 0x02, 0x00, 0x05, 0x14, 0x00, 0x00, // TP, SR, RP of Group0
-            0x1E, 0x1E, 0x00, 0x00, 0x00, 0x00, 0x01, // TP, SR, RP of Group1
-            0x00, 0x02, 0x00, 0x05, 0x14, 0x00, 0x00, // TP, SR, RP of Group2
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group3
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group4
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group5
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group6
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group7
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group8
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group9
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group10
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group11
-            0x24, 0x22, 0x22, 0x22, 0x23, 0x32, 0x00, 0x00, 0x00, // FR, XON
-            0x22, 0x17, 0x41, 0xAE, 0x32, 0x28, // EOPT VGH VSH1 VSH2 VSL VCOM
-        ]
+    /// fast full refresh lut, a shorter waveform than `black_white_lut` that leaves more ghosting
+    pub fn fast_lut(&self) -> Option<&'static [u8; 159]> {
+        self.panel.fast_lut
     }
+}
+
+// 2.9" full refresh lut
+const LUT_2IN9_BLACK_WHITE: [u8; 159] = [
+    0x80, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, // VS L0
+    0x10, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, // VS L1
+    0x80, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, // VS L2
+    0x10, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, // VS L3
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L4
+    0x14, 0x08, 0x00, 0x00, 0x00, 0x00, 0x01, // TP, SR, RP of Group0
+    0x0A, 0x0A, 0x00, 0x0A, 0x0A, 0x00, 0x01, // TP, SR, RP of Group1
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group2
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group3
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group4
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group5
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group6
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group7
+    0x14, 0x08, 0x00, 0x01, 0x00, 0x00, 0x01, // TP, SR, RP of Group8
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // TP, SR, RP of Group9
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group10
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group11
+    0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x00, 0x00, 0x00, //FR, XON
+    0x22, 0x17, 0x41, 0x00, 0x32, 0x36, // EOPT VGH VSH1 VSH2 VSL VCOM
+];
+
+// 2.9" fast full refresh, derived from `LUT_2IN9_BLACK_WHITE` rather than taken from the vendor:
+// the phases of group 0, 1 and 8 are halved and nothing repeats, so the waveform is
+// 49 frames instead of 194, about a quarter as long. not timed on the panel,
+// and it leaves more ghosting than the default
+const LUT_2IN9_FAST: [u8; 159] = [
+    0x80, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, // VS L0
+    0x10, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, // VS L1
+    0x80, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, // VS L2
+    0x10, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, // VS L3
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L4
+    0x0A, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group0
+    0x05, 0x05, 0x00, 0x05, 0x05, 0x00, 0x00, // TP, SR, RP of Group1
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group2
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group3
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group4
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group5
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group6
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group7
+    0x0A, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, // TP, SR, RP of Group8
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group9
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group10
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group11
+    0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x00, 0x00, 0x00, //FR, XON
+    0x22, 0x17, 0x41, 0x00, 0x32, 0x36, // EOPT VGH VSH1 VSH2 VSL VCOM
+];
+
+// 2.9" 4 level gray lut
+const LUT_2IN9_GRAY4: [u8; 159] = [
+    0x00, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L0
+    0x20, 0x60, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L1
+    0x28, 0x60, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L2
+    0x2A, 0x60, 0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L3
+    0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L4
+    0x00, 0x02, 0x00, 0x05, 0x14, 0x00, 0x00, // TP, SR, RP of Group0
+    0x1E, 0x1E, 0x00, 0x00, 0x00, 0x00, 0x01, // TP, SR, RP of Group1
+    0x00, 0x02, 0x00, 0x05, 0x14, 0x00, 0x00, // TP, SR, RP of Group2
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group3
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group4
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group5
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group6
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group7
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group8
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group9
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group10
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group11
+    0x24, 0x22, 0x22, 0x22, 0x23, 0x32, 0x00, 0x00, 0x00, // FR, XON
+    0x22, 0x17, 0x41, 0xAE, 0x32, 0x28, // EOPT VGH VSH1 VSH2 VSL VCOM
+];
 
-    fn partial_update_lut(&self) -> [u8; 159] {
-        [
-            0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L0
-            0x80, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L1
-            0x40, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L2
-            0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L3
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L4
-            0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // TP, SR, RP of Group0
-            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group1
-            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group2
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group3
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group4
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group5
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group6
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group7
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group8
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group9
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group10
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group11
-            0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x00, 0x00, 0x00, // FR, XON
-            0x22, 0x17, 0x41, 0xB0, 0x32, 0x36, // EOPT VGH VSH1 VSH2 VSL VCOM
+// 2.9" partial update lut
+const LUT_2IN9_PARTIAL: [u8; 159] = [
+    0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L0
+    0x80, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L1
+    0x40, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L2
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L3
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // VS L4
+    0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // TP, SR, RP of Group0
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group1
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group2
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group3
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group4
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group5
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group6
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group7
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group8
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group9
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group10
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // TP, SR, RP of Group11
+    0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x00, 0x00, 0x00, // FR, XON
+    0x22, 0x17, 0x41, 0xB0, 0x32, 0x36, // EOPT VGH VSH1 VSH2 VSL VCOM
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::Gray2Color;
+    use alloc::rc::Rc;
+    use core::convert::Infallible;
+
+    // level of dc and the bytes written with it, shared by the spi and dc mocks
+    #[derive(Default)]
+    struct Bus {
+        dc: bool,
+        written: Vec<(bool, u8)>,
+    }
+
+    type SharedBus = Rc<RefCell<Bus>>;
+
+    struct MockSpi(SharedBus);
+
+    impl embedded_hal::spi::ErrorType for MockSpi {
+        type Error = Infallible;
+    }
+
+    impl SpiBus for MockSpi {
+        fn read(&mut self, _words: &mut [u8]) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+            let mut bus = self.0.borrow_mut();
+            let dc = bus.dc;
+            bus.written.extend(words.iter().map(|&byte| (dc, byte)));
+            Ok(())
+        }
+
+        fn transfer(&mut self, _read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+            self.write(write)
+        }
+
+        fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+            self.write(words)
+        }
+
+        fn flush(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    struct MockDc(SharedBus);
+
+    impl embedded_hal::digital::ErrorType for MockDc {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockDc {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().dc = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().dc = true;
+            Ok(())
+        }
+    }
+
+    // power and reset are ignored, busy is never set
+    struct MockPin;
+
+    impl embedded_hal::digital::ErrorType for MockPin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockPin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    impl InputPin for MockPin {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(false)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(true)
+        }
+    }
+
+    struct MockDelay;
+
+    impl DelayNs for MockDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    type MockEPaper = EPaper<MockSpi, MockDc, MockPin, MockPin, MockPin, MockDelay>;
+
+    fn mock(panel: &PanelDescriptor) -> (MockEPaper, SharedBus) {
+        let bus = SharedBus::default();
+        let ep = EPaper::new(
+            panel,
+            MockSpi(bus.clone()),
+            MockPin,
+            MockPin,
+            MockPin,
+            MockDc(bus.clone()),
+            MockDelay,
+        );
+        (ep, bus)
+    }
+
+    // a command and its data as they are written, dc is low for the command
+    fn cmd(cmd: u8, data: &[u8]) -> Vec<(bool, u8)> {
+        let mut stream = vec![(false, cmd)];
+        stream.extend(data.iter().map(|&byte| (true, byte)));
+        stream
+    }
+
+    // split a recorded stream into commands and their data
+    fn commands(stream: &[(bool, u8)]) -> Vec<(u8, Vec<u8>)> {
+        let mut commands: Vec<(u8, Vec<u8>)> = Vec::new();
+        for &(data, byte) in stream {
+            match commands.last_mut() {
+                Some((_, bytes)) if data => bytes.push(byte),
+                _ => commands.push((byte, Vec::new())),
+            }
+        }
+        commands
+    }
+
+    // data of the first `cmd` in the stream
+    fn data_of(stream: &[(bool, u8)], cmd: u8) -> Vec<u8> {
+        commands(stream)
+            .into_iter()
+            .find(|(c, _)| *c == cmd)
+            .map(|(_, data)| data)
+            .unwrap_or_else(|| panic!("no command 0x{:02x}", cmd))
+    }
+
+    #[test]
+    fn init_black_white_command_stream() {
+        let (ep, bus) = mock(&EPD_2IN9_V2);
+        ep.init_black_white().unwrap();
+
+        let lut = &LUT_2IN9_BLACK_WHITE;
+        let white = vec![0xff; 16 * 296];
+        let expected = [
+            // soft reset
+            cmd(0x12, &[]),
+            // 296 gate lines
+            cmd(0x01, &[0x27, 0x01, 0x00]),
+            cmd(0x11, &[0x03]),
+            cmd(0x21, &[0x00, 0x80]),
+            // 16 bytes by 296 lines
+            cmd(0x44, &[0x00, 0x0f]),
+            cmd(0x45, &[0x00, 0x00, 0x27, 0x01]),
+            cmd(0x4e, &[0x00]),
+            cmd(0x4f, &[0x00, 0x00]),
+            cmd(0x32, &lut[..153]),
+            cmd(0x3f, &[lut[153]]),
+            cmd(0x03, &[lut[154]]),
+            cmd(0x04, &lut[155..158]),
+            cmd(0x2c, &[lut[158]]),
+            // clear screen
+            cmd(0x24, &white),
+            cmd(0x26, &white),
+            cmd(0x22, &[0xc7]),
+            cmd(0x20, &[]),
         ]
+        .concat();
+        assert_eq!(bus.borrow().written, expected);
+    }
+
+    #[test]
+    fn gray4_ram_window_follows_panel_size() {
+        // 2.13" panel, 122 source lines are 16 bytes with the last one padded
+        let panel = PanelDescriptor {
+            name: "2.13\"",
+            width: 250,
+            height: 122,
+            ..EPD_2IN9_V2
+        };
+        let (ep, bus) = mock(&panel);
+        ep.init_gray4().unwrap();
+        let gray4 = bus.borrow().written.clone();
+        assert_eq!(data_of(&gray4, 0x01), vec![0xf9, 0x00, 0x00]);
+        assert_eq!(data_of(&gray4, 0x44), vec![0x00, 0x0f]);
+        assert_eq!(data_of(&gray4, 0x45), vec![0x00, 0x00, 0xf9, 0x00]);
+
+        // black white mode uses the same window
+        bus.borrow_mut().written.clear();
+        ep.init_black_white().unwrap();
+        let black_white = bus.borrow().written.clone();
+        assert_eq!(data_of(&black_white, 0x44), data_of(&gray4, 0x44));
+        assert_eq!(data_of(&black_white, 0x45), data_of(&gray4, 0x45));
+    }
+
+    #[test]
+    fn rotations_keep_ram_scan_and_turn_the_frame() {
+        // 2.13" panel, 122 source lines are 16 bytes with the padding at the end of each row
+        let panel = PanelDescriptor {
+            name: "2.13\"",
+            width: 250,
+            height: 122,
+            ..EPD_2IN9_V2
+        };
+        let size = Size::new(panel.width as u32, panel.height as u32);
+        // byte of the frame holding the drawing origin, and its value
+        let cases = [
+            (Rotation::Rotate0, 249 * 16, 0x7f),
+            (Rotation::Rotate90, 249 * 16 + 15, 0xbf),
+            (Rotation::Rotate180, 15, 0xbf),
+            (Rotation::Rotate270, 0, 0x7f),
+        ];
+        for (rotation, index, value) in cases {
+            let (ep, bus) = mock(&panel);
+            ep.set_rotation(rotation);
+            ep.init_black_white().unwrap();
+            let stream = bus.borrow().written.clone();
+            assert_eq!(data_of(&stream, 0x11), vec![0x03], "{:?}", rotation);
+            assert_eq!(data_of(&stream, 0x44), vec![0x00, 0x0f], "{:?}", rotation);
+            assert_eq!(
+                data_of(&stream, 0x45),
+                vec![0x00, 0x00, 0xf9, 0x00],
+                "{:?}",
+                rotation
+            );
+            assert_eq!(data_of(&stream, 0x4e), vec![0x00], "{:?}", rotation);
+            assert_eq!(data_of(&stream, 0x4f), vec![0x00, 0x00], "{:?}", rotation);
+
+            let mut canvas = Canvas::with_rotation(&size, rotation);
+            canvas.set_pixel(Point::zero(), Gray2Color::Black);
+            bus.borrow_mut().written.clear();
+            ep.display_black_white(&canvas.render_black_white())
+                .unwrap();
+            let frame = data_of(&bus.borrow().written, 0x24);
+            let mut expected = vec![0xff; 16 * 250];
+            expected[index] = value;
+            assert_eq!(frame, expected, "{:?}", rotation);
+        }
+    }
+
+    #[test]
+    fn black_white_red_uses_panel_source_output() {
+        for panel in [EPD_2IN9_V2, EPD_4IN2_V2] {
+            let (ep, bus) = mock(&panel);
+            ep.init_black_white().unwrap();
+            bus.borrow_mut().written.clear();
+            let frame = vec![0xff; ep.frame_len()];
+            ep.display_black_white_red(&frame, &frame).unwrap();
+            let stream = bus.borrow().written.clone();
+            assert_eq!(
+                data_of(&stream, 0x21),
+                vec![0x00, panel.source_output],
+                "{}",
+                panel.name
+            );
+        }
+    }
+
+    // the frame writes and the refresh of a partial update, in order
+    fn partial_frames(stream: &[(bool, u8)]) -> Vec<(u8, Vec<u8>)> {
+        commands(stream)
+            .into_iter()
+            .filter(|(cmd, _)| matches!(cmd, 0x24 | 0x26 | 0x22 | 0x20))
+            .skip_while(|(cmd, _)| *cmd != 0x24 && *cmd != 0x26)
+            .collect()
+    }
+
+    #[test]
+    fn partial_update_writes_old_frame_first() {
+        let (ep, bus) = mock(&EPD_2IN9_V2);
+        let len = 16 * 296;
+        let (a, b, c) = (vec![0xaa; len], vec![0x55; len], vec![0x0f; len]);
+        let partial = EPD_2IN9_V2.partial_update;
+
+        // the old frame is unknown before the first full refresh
+        ep.display_partial(&a).unwrap();
+        let stream = core::mem::take(&mut bus.borrow_mut().written);
+        assert_eq!(
+            partial_frames(&stream),
+            vec![(0x24, a.clone()), (0x22, vec![partial]), (0x20, vec![])]
+        );
+
+        ep.init_black_white().unwrap();
+        ep.display_black_white(&a).unwrap();
+        bus.borrow_mut().written.clear();
+        // the first partial update after a full refresh starts from its frame
+        ep.display_partial(&b).unwrap();
+        ep.display_partial(&c).unwrap();
+        let stream = core::mem::take(&mut bus.borrow_mut().written);
+        assert_eq!(
+            partial_frames(&stream),
+            vec![
+                (0x26, a),
+                (0x24, b.clone()),
+                (0x22, vec![partial]),
+                (0x20, vec![]),
+                // init of the next partial update
+                (0x22, vec![0xc0]),
+                (0x20, vec![]),
+                (0x26, b),
+                (0x24, c),
+                (0x22, vec![partial]),
+                (0x20, vec![]),
+            ]
+        );
+    }
+
+    // bit by bit split of the implementation before `GRAY4_SPLIT`
+    fn split_bits(a: u8, b: u8) -> (u8, u8) {
+        let (mut high, mut low) = (0u8, 0u8);
+        for (i, byte) in [a, b].into_iter().enumerate() {
+            for pixel in 0..4 {
+                let shift = 6 - pixel * 2;
+                let bit = 7 - (i * 4 + pixel);
+                high |= (byte >> (shift + 1) & 1) << bit;
+                low |= (byte >> shift & 1) << bit;
+            }
+        }
+        (high, low)
+    }
+
+    #[test]
+    fn parse_gray4_data_matches_bit_split() {
+        // every pair of bytes
+        let data: Vec<u8> = (0..=u16::MAX).flat_map(|pair| pair.to_be_bytes()).collect();
+        let (data1, data2) = MockEPaper::parse_gray4_data(&data);
+        assert_eq!(data1.len(), data.len() / 2);
+        assert_eq!(data2.len(), data.len() / 2);
+        for (i, chunk) in data.chunks(2).enumerate() {
+            let expected = split_bits(chunk[0], chunk[1]);
+            assert_eq!((data1[i], data2[i]), expected, "{:02x?}", chunk);
+        }
+        // black, dark gray, light gray and white
+        assert_eq!(
+            MockEPaper::parse_gray4_data(&[0b1101_1000, 0xff]),
+            (vec![0b1010_1111], vec![0b1100_1111])
+        );
+    }
+
+    #[test]
+    fn parse_gray4_data_pads_odd_byte_with_white() {
+        assert_eq!(
+            MockEPaper::parse_gray4_data(&[0xff]),
+            (vec![0xf0], vec![0xf0])
+        );
+    }
+
+    #[test]
+    fn gray4_frames_at_122_and_200_pixel_rows() {
+        // ram rows of 122 pixels as on the 2.13" panel, padded to 128, and 200 as on the 1.54"
+        for height in [122u32, 200] {
+            let width = 4;
+            let mut canvas = Canvas::new(&Size::new(width, height));
+            // the last pixel of every ram row, next to the padding
+            for x in 0..width as i32 {
+                canvas.set_pixel(Point::new(x, height as i32 - 1), Gray2Color::Black);
+            }
+            let (data1, data2) = MockEPaper::parse_gray4_data(&canvas.render_gray());
+            let row_len = height.div_ceil(8) as usize;
+            assert_eq!(data1.len(), width as usize * row_len, "height {}", height);
+            assert_eq!(data2.len(), width as usize * row_len, "height {}", height);
+            for (i, (&high, &low)) in data1.iter().zip(data2.iter()).enumerate() {
+                // black is 0b11, the padding is white 0b00
+                let expected = if i % row_len == row_len - 1 {
+                    0x80 >> ((height - 1) % 8)
+                } else {
+                    0
+                };
+                assert_eq!(
+                    (high, low),
+                    (expected, expected),
+                    "height {} byte {}",
+                    height,
+                    i
+                );
+            }
+        }
     }
 }
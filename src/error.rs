@@ -1,8 +1,132 @@
 use alloc::string::String;
+#[cfg(feature = "esp32")]
+use blocking_network_stack::IoError;
+use core::convert::Infallible;
+#[cfg(feature = "esp32")]
 use esp_hal::i2c::master::Error as i2cError;
+#[cfg(feature = "esp32")]
+use esp_hal::spi::Error as spiError;
+#[cfg(feature = "esp32")]
+use esp_wifi::wifi::WifiError;
+#[cfg(feature = "esp32")]
+use esp_wifi::InitializationError;
 
 #[derive(Debug)]
 pub enum Error {
+    #[cfg(feature = "esp32")]
     I2cError(i2cError),
+    #[cfg(feature = "esp32")]
+    SpiError(spiError),
+    #[cfg(feature = "esp32")]
+    NetworkError(IoError),
+    #[cfg(feature = "esp32")]
+    WifiError(WifiError),
+    #[cfg(feature = "esp32")]
+    WifiInitError(InitializationError),
     SimpleError(String),
+    CrcMismatch {
+        expected: u8,
+        got: u8,
+    },
+    // `word` is the index of the first bad word in the frame
+    FrameCrcMismatch {
+        word: usize,
+        expected: u8,
+        got: u8,
+    },
+    Timeout,
+    PoweredOff,
+    // measurement is read before it is started
+    NotStarted,
+}
+
+#[cfg(feature = "esp32")]
+impl From<i2cError> for Error {
+    fn from(err: i2cError) -> Self {
+        Error::I2cError(err)
+    }
+}
+
+#[cfg(feature = "esp32")]
+impl From<spiError> for Error {
+    fn from(err: spiError) -> Self {
+        Error::SpiError(err)
+    }
+}
+
+#[cfg(feature = "esp32")]
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Self {
+        Error::NetworkError(err)
+    }
+}
+
+#[cfg(feature = "esp32")]
+impl From<WifiError> for Error {
+    fn from(err: WifiError) -> Self {
+        Error::WifiError(err)
+    }
+}
+
+#[cfg(feature = "esp32")]
+impl From<InitializationError> for Error {
+    fn from(err: InitializationError) -> Self {
+        Error::WifiInitError(err)
+    }
+}
+
+impl From<Infallible> for Error {
+    fn from(err: Infallible) -> Self {
+        match err {}
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            #[cfg(feature = "esp32")]
+            Error::I2cError(err) => write!(f, "i2c error: {}", err),
+            // spi error has no `Display`
+            #[cfg(feature = "esp32")]
+            Error::SpiError(err) => write!(f, "spi error: {:?}", err),
+            #[cfg(feature = "esp32")]
+            Error::NetworkError(err) => write!(f, "network error: {:?}", err),
+            #[cfg(feature = "esp32")]
+            Error::WifiError(err) => write!(f, "wifi error: {:?}", err),
+            #[cfg(feature = "esp32")]
+            Error::WifiInitError(err) => write!(f, "wifi init error: {:?}", err),
+            Error::SimpleError(msg) => write!(f, "{}", msg),
+            Error::CrcMismatch { expected, got } => {
+                write!(
+                    f,
+                    "crc mismatch, expected 0x{:02x}, got 0x{:02x}",
+                    expected, got
+                )
+            }
+            Error::FrameCrcMismatch {
+                word,
+                expected,
+                got,
+            } => {
+                write!(
+                    f,
+                    "crc mismatch at word {}, expected 0x{:02x}, got 0x{:02x}",
+                    word, expected, got
+                )
+            }
+            Error::Timeout => write!(f, "timeout"),
+            Error::PoweredOff => write!(f, "device is powered off"),
+            Error::NotStarted => write!(f, "measurement not started"),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "esp32")]
+            Error::I2cError(err) => Some(err),
+            _ => None,
+        }
+    }
 }
@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 extern crate alloc;
 
 pub mod scd41;
@@ -6,5 +6,17 @@ pub mod e_paper;
 pub mod error;
 pub mod canvas;
 pub mod utils;
+#[cfg(feature = "esp32")]
+pub mod logger;
+#[cfg(feature = "esp32")]
+pub mod button;
 pub mod net;
+pub mod nvs;
+#[cfg(feature = "esp32")]
+pub mod device;
+#[cfg(feature = "esp32")]
+pub mod power;
+pub mod smooth;
+pub mod selftest;
+pub mod alert;
 pub mod config;
@@ -0,0 +1,218 @@
+use core::cell::Cell;
+use critical_section::Mutex;
+#[cfg(not(feature = "log-no-timestamp"))]
+use esp_hal::time::Instant;
+use log::LevelFilter;
+
+const MAX_MODULE_OVERRIDES: usize = 8;
+
+static CONFIG: Mutex<Cell<LogConfig>> = Mutex::new(Cell::new(LogConfig::new()));
+
+/// global log level with per-module overrides, e.g.
+/// `LogConfig::new().module("co2_monitor::e_paper", LevelFilter::Debug)`
+#[derive(Debug, Clone, Copy)]
+pub struct LogConfig {
+    level: LevelFilter,
+    // (module prefix, level), the longest matching prefix wins
+    modules: [(&'static str, LevelFilter); MAX_MODULE_OVERRIDES],
+    module_count: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogConfig {
+    pub const fn new() -> Self {
+        LogConfig {
+            level: LevelFilter::Info,
+            modules: [("", LevelFilter::Off); MAX_MODULE_OVERRIDES],
+            module_count: 0,
+        }
+    }
+
+    /// level of modules without override
+    pub fn level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// override level of modules starting with `prefix`,
+    /// at most 8 overrides are kept, the rest are ignored
+    pub fn module(mut self, prefix: &'static str, level: LevelFilter) -> Self {
+        if self.module_count < MAX_MODULE_OVERRIDES {
+            self.modules[self.module_count] = (prefix, level);
+            self.module_count += 1;
+        }
+        self
+    }
+
+    fn level_of(&self, target: &str) -> LevelFilter {
+        self.modules[..self.module_count]
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules[..self.module_count]
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.level, |a, b| a.max(b))
+    }
+}
+
+fn config() -> LogConfig {
+    critical_section::with(|cs| CONFIG.borrow(cs).get())
+}
+
+fn set_config(config: LogConfig) {
+    critical_section::with(|cs| CONFIG.borrow(cs).set(config));
+    // let records of the overridden modules pass the filter of `log` macros
+    log::set_max_level(config.max_level());
+}
+
+/// change global log level at runtime, module overrides are kept
+pub fn set_log_level(level: LevelFilter) {
+    set_config(config().level(level));
+}
+
+pub fn log_level() -> LevelFilter {
+    config().level
+}
+
+/// print logs as `[12345ms][INFO][co2_monitor::scd41] message`,
+/// disable the timestamp with feature `log-no-timestamp`
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= config().level_of(metadata.target())
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        #[cfg(not(feature = "log-no-timestamp"))]
+        esp_println::println!(
+            "[{:06}ms][{}][{}] {}",
+            Instant::now().duration_since_epoch().as_millis(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        #[cfg(feature = "log-no-timestamp")]
+        esp_println::println!(
+            "[{}][{}] {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        #[cfg(feature = "log-buffer")]
+        buffer::write(record);
+    }
+
+    fn flush(&self) {}
+}
+
+/// use this logger instead of `esp_println::logger`
+pub fn init_logger(level: LevelFilter) {
+    init_logger_with(LogConfig::new().level(level));
+}
+
+pub fn init_logger_with(config: LogConfig) {
+    // only fails if a logger is set already
+    let _ = log::set_logger(&LOGGER);
+    set_config(config);
+}
+
+/// print the logs kept in memory, only available with feature `log-buffer`
+pub fn dump_log_buffer() {
+    #[cfg(feature = "log-buffer")]
+    buffer::dump();
+}
+
+#[cfg(feature = "log-buffer")]
+mod buffer {
+    use core::cell::RefCell;
+    use core::fmt::Write;
+    use critical_section::Mutex;
+
+    const LOG_BUFFER_SIZE: usize = 4096;
+
+    static LOG_BUFFER: Mutex<RefCell<RingBuffer>> = Mutex::new(RefCell::new(RingBuffer {
+        data: [0; LOG_BUFFER_SIZE],
+        pos: 0,
+        wrapped: false,
+    }));
+
+    /// bytes ring buffer, overwrites the oldest bytes when full
+    struct RingBuffer {
+        data: [u8; LOG_BUFFER_SIZE],
+        // next position to write
+        pos: usize,
+        wrapped: bool,
+    }
+
+    impl Write for RingBuffer {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            for b in s.bytes() {
+                self.data[self.pos] = b;
+                self.pos += 1;
+                if self.pos == LOG_BUFFER_SIZE {
+                    self.pos = 0;
+                    self.wrapped = true;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    pub(super) fn write(record: &log::Record) {
+        critical_section::with(|cs| {
+            // skip if it's being dumped
+            if let Ok(mut buffer) = LOG_BUFFER.borrow(cs).try_borrow_mut() {
+                let _ = writeln!(
+                    buffer,
+                    "[{}][{}] {}",
+                    record.level(),
+                    record.target(),
+                    record.args()
+                );
+            }
+        });
+    }
+
+    pub(super) fn dump() {
+        critical_section::with(|cs| {
+            let Ok(buffer) = LOG_BUFFER.borrow(cs).try_borrow() else {
+                return;
+            };
+            let (older, newer) = if buffer.wrapped {
+                buffer.data.split_at(buffer.pos)
+            } else {
+                (&[][..], &buffer.data[..buffer.pos])
+            };
+            // the first line may be overwritten partially, skip it
+            let older = match older.iter().position(|b| *b == b'\n') {
+                Some(idx) => &older[idx + 1..],
+                None => &[][..],
+            };
+            esp_println::println!("---- log buffer ----");
+            for part in [older, newer] {
+                esp_println::print!(
+                    "{}",
+                    core::str::from_utf8(part).unwrap_or("<invalid utf8>\n")
+                );
+            }
+            esp_println::println!("---- end of log buffer ----");
+        });
+    }
+}
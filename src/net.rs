@@ -1,29 +1,452 @@
-use alloc::vec;
+use crate::error::Error;
+use crate::scd41::MeasureResult;
+#[cfg(feature = "esp32")]
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
+#[cfg(feature = "esp32")]
+use blocking_network_stack::UdpSocket;
+#[cfg(feature = "esp32")]
+use esp_hal::delay::Delay;
+#[cfg(feature = "esp32")]
+use esp_hal::time::Instant;
+#[cfg(feature = "esp32")]
+use log::{debug, warn};
+#[cfg(feature = "esp32")]
+use smoltcp::phy::Device;
 use smoltcp::socket::udp;
 
-pub fn parse_ip(ip: &str) -> [u8; 4] {
+pub mod http;
+pub mod mqtt;
+#[cfg(feature = "esp32")]
+pub mod ntp;
+
+/// parse a dotted ipv4 address like `192.168.1.2`
+pub fn parse_ip(ip: &str) -> Result<[u8; 4], Error> {
+    let invalid = || Error::SimpleError(format!("invalid ip: {}", ip));
     let mut result = [0u8; 4];
-    for (idx, octet) in ip.split(".").into_iter().enumerate() {
-        result[idx] = u8::from_str_radix(octet, 10).unwrap();
+    let mut octets = ip.split('.');
+    for byte in result.iter_mut() {
+        *byte = octets
+            .next()
+            .and_then(|octet| octet.parse().ok())
+            .ok_or_else(invalid)?;
+    }
+    if octets.next().is_some() {
+        return Err(invalid());
+    }
+    Ok(result)
+}
+
+/// connection state shown on the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetStatus {
+    Connected,
+    // wifi is connected but there is no ip yet
+    Connecting,
+    Disconnected,
+    // connected but the last metric was not sent
+    SendFailed,
+}
+
+impl NetStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            NetStatus::Connected => "WIFI OK",
+            NetStatus::Connecting => "WIFI ...",
+            NetStatus::Disconnected => "NO WIFI",
+            NetStatus::SendFailed => "SEND ERR",
+        }
+    }
+}
+
+/// address and port of a server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Endpoint {
+    pub ip: [u8; 4],
+    pub port: u16,
+}
+
+#[cfg(feature = "esp32")]
+pub fn send_metric<D: Device>(
+    socket: &mut UdpSocket<D>,
+    server: &Endpoint,
+    encoder: &dyn MetricEncoder,
+    m: &MeasureResult,
+) -> Result<(), Error> {
+    let mut data = Vec::new();
+    encoder.encode(m, &mut data);
+    send_payload(socket, server, data.as_slice())
+}
+
+#[cfg(feature = "esp32")]
+fn send_payload<D: Device>(
+    socket: &mut UdpSocket<D>,
+    server: &Endpoint,
+    data: &[u8],
+) -> Result<(), Error> {
+    let addr = blocking_network_stack::ipv4::Ipv4Addr::from(server.ip);
+    debug!("start to send metric to udp://{}:{}", addr, server.port);
+    socket.send(addr.into(), server.port, data)?;
+    Ok(())
+}
+
+/// destination of measure results, e.g. `UdpSink`
+pub trait MetricSink {
+    fn send(&mut self, m: &MeasureResult) -> Result<(), Error>;
+
+    /// send a result measured `age_ms` ago, the age is dropped by default
+    fn send_with_age(&mut self, m: &MeasureResult, age_ms: u32) -> Result<(), Error> {
+        let _ = age_ms;
+        self.send(m)
+    }
+
+    /// housekeeping like keepalive, call it in the main loop
+    fn poll(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// send encoded results to `server` over udp, the socket should be bound already
+#[cfg(feature = "esp32")]
+pub struct UdpSink<'a, 's, 'n: 's, D: Device> {
+    socket: UdpSocket<'s, 'n, D>,
+    server: Endpoint,
+    encoder: &'a dyn MetricEncoder,
+}
+
+#[cfg(feature = "esp32")]
+impl<'a, 's, 'n: 's, D: Device> UdpSink<'a, 's, 'n, D> {
+    pub fn new(
+        socket: UdpSocket<'s, 'n, D>,
+        server: Endpoint,
+        encoder: &'a dyn MetricEncoder,
+    ) -> Self {
+        UdpSink {
+            socket,
+            server,
+            encoder,
+        }
+    }
+
+    /// the socket is still usable for other traffic, e.g. ntp
+    pub fn socket(&mut self) -> &mut UdpSocket<'s, 'n, D> {
+        &mut self.socket
+    }
+}
+
+#[cfg(feature = "esp32")]
+impl<D: Device> MetricSink for UdpSink<'_, '_, '_, D> {
+    fn send(&mut self, m: &MeasureResult) -> Result<(), Error> {
+        send_metric(&mut self.socket, &self.server, self.encoder, m)
+    }
+
+    fn send_with_age(&mut self, m: &MeasureResult, age_ms: u32) -> Result<(), Error> {
+        let mut data = Vec::new();
+        self.encoder.encode_with_age(m, age_ms, &mut data);
+        send_payload(&mut self.socket, &self.server, data.as_slice())
+    }
+}
+
+/// send metric, retry up to `attempts` times with exponential backoff starting from `base_delay_ms`
+#[cfg(feature = "esp32")]
+pub fn send_metric_retry<D: Device>(
+    socket: &mut UdpSocket<D>,
+    server: &Endpoint,
+    encoder: &dyn MetricEncoder,
+    m: &MeasureResult,
+    attempts: u32,
+    base_delay_ms: u32,
+) -> Result<(), Error> {
+    let delay = Delay::new();
+    let mut wait_ms = base_delay_ms;
+    let mut attempt = 1;
+    loop {
+        match send_metric(socket, server, encoder, m) {
+            Ok(_) => return Ok(()),
+            Err(err) if attempt >= attempts => return Err(err),
+            Err(err) => {
+                warn!(
+                    "send metric error: {:?}, retry in {}ms, attempt {}/{}",
+                    err, wait_ms, attempt, attempts
+                );
+            }
+        }
+        delay.delay_millis(wait_ms);
+        // let stack make progress, e.g. reconnect
+        socket.work();
+        wait_ms = wait_ms.saturating_mul(2);
+        attempt += 1;
     }
-    result
 }
 
-pub struct SocketBuff {
-    pub rx_meta: Vec<udp::PacketMetadata>,
-    pub rx_buffer: [u8; 1500],
-    pub tx_meta: Vec<udp::PacketMetadata>,
-    pub tx_buffer: [u8; 1500],
+/// buffers of an udp socket, `META` is the number of datagrams that can be queued
+pub struct SocketBuff<const RX: usize = 1500, const TX: usize = 1500, const META: usize = 1> {
+    pub rx_meta: [udp::PacketMetadata; META],
+    pub rx_buffer: [u8; RX],
+    pub tx_meta: [udp::PacketMetadata; META],
+    pub tx_buffer: [u8; TX],
 }
 
 impl SocketBuff {
+    /// 1500 bytes buffers with a single datagram slot
     pub fn new() -> SocketBuff {
+        Self::new_sized()
+    }
+}
+
+impl<const RX: usize, const TX: usize, const META: usize> SocketBuff<RX, TX, META> {
+    pub fn new_sized() -> Self {
         SocketBuff {
-            tx_meta: vec![udp::PacketMetadata::EMPTY.clone()],
-            tx_buffer: [0u8; 1500],
-            rx_meta: vec![udp::PacketMetadata::EMPTY.clone()],
-            rx_buffer: [0u8; 1500],
+            tx_meta: [udp::PacketMetadata::EMPTY; META],
+            tx_buffer: [0u8; TX],
+            rx_meta: [udp::PacketMetadata::EMPTY; META],
+            rx_buffer: [0u8; RX],
+        }
+    }
+}
+
+impl<const RX: usize, const TX: usize, const META: usize> Default for SocketBuff<RX, TX, META> {
+    fn default() -> Self {
+        Self::new_sized()
+    }
+}
+
+/// serialize a measure result to the metric payload
+pub trait MetricEncoder {
+    fn encode(&self, m: &MeasureResult, buf: &mut Vec<u8>);
+
+    /// encode a result measured `age_ms` ago, the age is dropped by default
+    fn encode_with_age(&self, m: &MeasureResult, age_ms: u32, buf: &mut Vec<u8>) {
+        let _ = age_ms;
+        self.encode(m, buf);
+    }
+}
+
+/// raw binary format, see `MeasureResult::to_bytes`
+pub struct BinaryEncoder;
+
+impl MetricEncoder for BinaryEncoder {
+    fn encode(&self, m: &MeasureResult, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(m.to_bytes().as_slice());
+    }
+
+    /// age(u32) in ms is appended, 14 bytes in total
+    fn encode_with_age(&self, m: &MeasureResult, age_ms: u32, buf: &mut Vec<u8>) {
+        self.encode(m, buf);
+        buf.extend_from_slice(age_ms.to_be_bytes().as_slice());
+    }
+}
+
+/// influxdb line protocol, e.g. `co2,sensor=esp temp=23.40,hum=41.20,co2=812i 1700000000000`
+///
+/// the timestamp is in ms, so write with `precision=ms`.
+/// without `clock`, the timestamp is left out and the server uses the time of arrival
+#[cfg(feature = "esp32")]
+pub struct InfluxEncoder {
+    pub sensor: &'static str,
+    pub clock: Option<ntp::Clock>,
+}
+
+#[cfg(feature = "esp32")]
+impl MetricEncoder for InfluxEncoder {
+    fn encode(&self, m: &MeasureResult, buf: &mut Vec<u8>) {
+        self.encode_with_age(m, 0, buf);
+    }
+
+    fn encode_with_age(&self, m: &MeasureResult, age_ms: u32, buf: &mut Vec<u8>) {
+        let line = format!(
+            "co2,sensor={} temp={:.2},hum={:.2},co2={}i",
+            self.sensor, m.temp, m.hum, m.co2_ppm
+        );
+        buf.extend_from_slice(line.as_bytes());
+        if let Some(clock) = &self.clock {
+            let timestamp = clock.now_ms() - age_ms as u64;
+            buf.extend_from_slice(format!(" {}", timestamp).as_bytes());
+        }
+        buf.push(b'\n');
+    }
+}
+
+/// json object, floats with one decimal, `null` if not a number
+///
+/// ```text
+/// {"co2":812,"temp":23.4,"hum":41.2}
+/// ```
+pub struct JsonEncoder;
+
+impl JsonEncoder {
+    fn number(value: f32) -> String {
+        if value.is_finite() {
+            format!("{:.1}", value)
+        } else {
+            String::from("null")
+        }
+    }
+}
+
+impl MetricEncoder for JsonEncoder {
+    fn encode(&self, m: &MeasureResult, buf: &mut Vec<u8>) {
+        let json = format!(
+            "{{\"co2\":{},\"temp\":{},\"hum\":{}}}",
+            m.co2_ppm,
+            Self::number(m.temp),
+            Self::number(m.hum)
+        );
+        buf.extend_from_slice(json.as_bytes());
+    }
+
+    /// `age_ms` is added as the last field
+    fn encode_with_age(&self, m: &MeasureResult, age_ms: u32, buf: &mut Vec<u8>) {
+        self.encode(m, buf);
+        buf.pop();
+        buf.extend_from_slice(format!(",\"age_ms\":{}}}", age_ms).as_bytes());
+    }
+}
+
+/// prometheus exposition format, e.g. for pushgateway
+///
+/// ```text
+/// # TYPE co2_ppm gauge
+/// co2_ppm{sensor="esp"} 812
+/// ```
+///
+/// to push to a pushgateway, post the output with `http::HttpSink` to `/metrics/job/<job>`,
+/// e.g. `HttpSink::new(socket, server, host, "/metrics/job/co2-monitor")`
+/// and `sink.post("text/plain", &body)`
+pub struct PrometheusEncoder {
+    pub sensor: Option<&'static str>,
+}
+
+impl MetricEncoder for PrometheusEncoder {
+    fn encode(&self, m: &MeasureResult, buf: &mut Vec<u8>) {
+        let labels = match self.sensor {
+            Some(sensor) => format!("{{sensor=\"{}\"}}", sensor),
+            None => String::new(),
+        };
+        let metrics = [
+            ("co2_ppm", format!("{}", m.co2_ppm)),
+            ("temperature_celsius", format!("{:.2}", m.temp)),
+            ("humidity_percent", format!("{:.2}", m.hum)),
+        ];
+        for (name, value) in metrics {
+            let text = format!("# TYPE {} gauge\n{}{} {}\n", name, name, labels, value);
+            buf.extend_from_slice(text.as_bytes());
+        }
+    }
+}
+
+/// bounded queue of measure results, kept while the network is down.
+/// the oldest result is dropped when full
+#[cfg(feature = "esp32")]
+pub struct MetricQueue {
+    queue: VecDeque<(MeasureResult, Instant)>,
+    capacity: usize,
+}
+
+#[cfg(feature = "esp32")]
+impl MetricQueue {
+    pub fn new(capacity: usize) -> Self {
+        MetricQueue {
+            queue: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, m: MeasureResult) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.queue.len() == self.capacity {
+            warn!("metric queue full, drop the oldest");
+            self.queue.pop_front();
+        }
+        self.queue.push_back((m, Instant::now()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// send queued results from the oldest with their age,
+    /// stop at the first error and keep the rest for next time
+    pub fn drain_to(&mut self, sink: &mut dyn MetricSink) -> Result<usize, Error> {
+        let mut sent = 0;
+        while let Some((m, measured_at)) = self.queue.front() {
+            let age_ms = measured_at.elapsed().as_millis() as u32;
+            sink.send_with_age(m, age_ms)?;
+            self.queue.pop_front();
+            sent += 1;
         }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ip_accepts_dotted_quad() {
+        assert!(matches!(parse_ip("192.168.1.2"), Ok([192, 168, 1, 2])));
+        assert!(matches!(parse_ip("0.0.0.0"), Ok([0, 0, 0, 0])));
+    }
+
+    #[test]
+    fn parse_ip_rejects_bad_addresses() {
+        for ip in [
+            "",
+            "192.168.1",
+            "192.168.1.2.3",
+            "192.168.1.256",
+            "192.168..2",
+            "METRIC_SERVER",
+        ] {
+            assert!(parse_ip(ip).is_err(), "{}", ip);
+        }
+    }
+
+    fn encode(encoder: &dyn MetricEncoder, m: &MeasureResult) -> String {
+        let mut buf = Vec::new();
+        encoder.encode(m, &mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn json_sample_reading() {
+        let m = MeasureResult {
+            co2_ppm: 812,
+            temp: 23.4,
+            hum: 41.2,
+        };
+        assert_eq!(
+            encode(&JsonEncoder, &m),
+            r#"{"co2":812,"temp":23.4,"hum":41.2}"#
+        );
+
+        let mut buf = Vec::new();
+        JsonEncoder.encode_with_age(&m, 1500, &mut buf);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            r#"{"co2":812,"temp":23.4,"hum":41.2,"age_ms":1500}"#
+        );
+    }
+
+    #[test]
+    fn json_not_a_number_is_null() {
+        let m = MeasureResult {
+            co2_ppm: 0,
+            temp: f32::NAN,
+            hum: f32::INFINITY,
+        };
+        assert_eq!(
+            encode(&JsonEncoder, &m),
+            r#"{"co2":0,"temp":null,"hum":null}"#
+        );
     }
 }
@@ -0,0 +1,200 @@
+#[cfg(feature = "esp32")]
+use super::{Endpoint, JsonEncoder, MetricEncoder, MetricSink};
+#[cfg(any(feature = "esp32", test))]
+use crate::error::Error;
+#[cfg(feature = "esp32")]
+use crate::scd41::MeasureResult;
+#[cfg(any(feature = "esp32", test))]
+use alloc::format;
+#[cfg(feature = "esp32")]
+use alloc::string::{String, ToString};
+#[cfg(feature = "esp32")]
+use alloc::vec::Vec;
+#[cfg(feature = "esp32")]
+use blocking_network_stack::Socket;
+#[cfg(feature = "esp32")]
+use embedded_io::{Read, ReadReady, Write};
+#[cfg(feature = "esp32")]
+use esp_hal::time::{Duration, Instant};
+#[cfg(any(feature = "esp32", test))]
+use log::debug;
+#[cfg(feature = "esp32")]
+use smoltcp::phy::Device;
+
+// the status line is short, more means it is not http
+#[cfg(feature = "esp32")]
+const MAX_STATUS_LINE_LEN: usize = 256;
+
+/// post results as json to an http endpoint, e.g. a webhook of a dashboard
+///
+/// a connection is opened for every send with `Connection: close`, the body has a fixed
+/// `Content-Length`. only the status line of the response is read, any 2xx is a success.
+/// plain http only, the tcp socket is created like the mqtt one
+#[cfg(feature = "esp32")]
+pub struct HttpSink<'s, 'n: 's, D: Device> {
+    socket: Socket<'s, 'n, D>,
+    server: Endpoint,
+    // sent as the host header, some servers route by it
+    host: String,
+    path: String,
+    timeout_ms: u64,
+}
+
+#[cfg(feature = "esp32")]
+impl<'s, 'n: 's, D: Device> HttpSink<'s, 'n, D> {
+    pub fn new(socket: Socket<'s, 'n, D>, server: Endpoint, host: &str, path: &str) -> Self {
+        HttpSink {
+            socket,
+            server,
+            host: host.to_string(),
+            path: path.to_string(),
+            timeout_ms: 10_000,
+        }
+    }
+
+    /// max wait for the status line after the request is sent, default is 10s
+    pub fn with_timeout(mut self, ms: u64) -> Self {
+        self.timeout_ms = ms;
+        self
+    }
+
+    /// post `body` and return the status code, the connection is closed afterwards
+    pub fn post(&mut self, content_type: &str, body: &[u8]) -> Result<u16, Error> {
+        let addr = blocking_network_stack::ipv4::Ipv4Addr::from(self.server.ip);
+        debug!(
+            "post {} bytes to http://{}:{}{}",
+            body.len(),
+            addr,
+            self.server.port,
+            self.path
+        );
+        self.socket.open(addr.into(), self.server.port)?;
+        let result = self.request(content_type, body);
+        self.socket.disconnect();
+        result
+    }
+
+    fn request(&mut self, content_type: &str, body: &[u8]) -> Result<u16, Error> {
+        let header = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            content_type,
+            body.len()
+        );
+        self.socket.write_all(header.as_bytes())?;
+        self.socket.write_all(body)?;
+        self.socket.flush()?;
+        let line = self.read_status_line()?;
+        parse_status(&line)
+    }
+
+    fn read_status_line(&mut self) -> Result<String, Error> {
+        let timeout = Duration::from_millis(self.timeout_ms);
+        let started = Instant::now();
+        let mut line = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            if let Some(end) = line.windows(2).position(|w| w == b"\r\n") {
+                line.truncate(end);
+                return Ok(String::from_utf8_lossy(&line).to_string());
+            }
+            if line.len() > MAX_STATUS_LINE_LEN {
+                return Err(Error::SimpleError("http status line too long".to_string()));
+            }
+            if started.elapsed() > timeout {
+                return Err(Error::Timeout);
+            }
+            self.socket.work();
+            if !self.socket.read_ready()? {
+                continue;
+            }
+            match self.socket.read(&mut buf)? {
+                0 => {
+                    return Err(Error::SimpleError(
+                        "http connection closed before response".to_string(),
+                    ))
+                }
+                len => line.extend_from_slice(&buf[..len]),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "esp32")]
+impl<D: Device> MetricSink for HttpSink<'_, '_, D> {
+    fn send(&mut self, m: &MeasureResult) -> Result<(), Error> {
+        let mut body = Vec::new();
+        JsonEncoder.encode(m, &mut body);
+        self.post("application/json", body.as_slice()).map(|_| ())
+    }
+
+    fn send_with_age(&mut self, m: &MeasureResult, age_ms: u32) -> Result<(), Error> {
+        let mut body = Vec::new();
+        JsonEncoder.encode_with_age(m, age_ms, &mut body);
+        self.post("application/json", body.as_slice()).map(|_| ())
+    }
+}
+
+// status code of e.g. `HTTP/1.1 204 No Content`, error if it is not 2xx
+#[cfg(any(feature = "esp32", test))]
+fn parse_status(line: &str) -> Result<u16, Error> {
+    let mut parts = line.split(' ');
+    let version = parts.next().unwrap_or_default();
+    let status = parts.next().and_then(|code| code.parse::<u16>().ok());
+    let Some(status) = status.filter(|_| version.starts_with("HTTP/")) else {
+        return Err(Error::SimpleError(format!(
+            "invalid http status line: {}",
+            line
+        )));
+    };
+    if !(200..300).contains(&status) {
+        return Err(Error::SimpleError(format!("http error: {}", line)));
+    }
+    debug!("http status: {}", status);
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_status_accepts_2xx() {
+        assert!(matches!(parse_status("HTTP/1.1 200 OK"), Ok(200)));
+        assert!(matches!(parse_status("HTTP/1.1 204 No Content"), Ok(204)));
+        assert!(matches!(parse_status("HTTP/1.0 201"), Ok(201)));
+    }
+
+    #[test]
+    fn parse_status_rejects_other_codes() {
+        for line in [
+            "HTTP/1.1 301 Moved Permanently",
+            "HTTP/1.1 500 Internal Server Error",
+        ] {
+            assert!(
+                matches!(parse_status(line), Err(Error::SimpleError(msg)) if msg.starts_with("http error")),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn parse_status_rejects_invalid_lines() {
+        for line in [
+            "",
+            "garbage",
+            "SSH-2.0-OpenSSH_9.6",
+            "HTTP/1.1",
+            "HTTP/1.1 OK",
+            "200 OK",
+        ] {
+            assert!(
+                matches!(parse_status(line), Err(Error::SimpleError(msg)) if msg.starts_with("invalid")),
+                "{}",
+                line
+            );
+        }
+    }
+}
@@ -0,0 +1,365 @@
+#[cfg(feature = "esp32")]
+use super::{Endpoint, JsonEncoder, MetricEncoder, MetricSink};
+#[cfg(feature = "esp32")]
+use crate::error::Error;
+#[cfg(feature = "esp32")]
+use crate::scd41::MeasureResult;
+#[cfg(feature = "esp32")]
+use alloc::format;
+#[cfg(feature = "esp32")]
+use alloc::string::{String, ToString};
+#[cfg(any(feature = "esp32", test))]
+use alloc::vec::Vec;
+#[cfg(feature = "esp32")]
+use blocking_network_stack::Socket;
+#[cfg(feature = "esp32")]
+use embedded_io::{Read, ReadReady, Write};
+#[cfg(feature = "esp32")]
+use esp_hal::time::{Duration, Instant};
+#[cfg(feature = "esp32")]
+use log::{debug, info, warn};
+#[cfg(feature = "esp32")]
+use smoltcp::phy::Device;
+
+#[cfg(any(feature = "esp32", test))]
+const PROTOCOL_LEVEL: u8 = 4;
+// clean session, no will
+#[cfg(any(feature = "esp32", test))]
+const CONNECT_FLAGS: u8 = 0x02;
+#[cfg(any(feature = "esp32", test))]
+const USERNAME_FLAG: u8 = 0x80;
+#[cfg(any(feature = "esp32", test))]
+const PASSWORD_FLAG: u8 = 0x40;
+#[cfg(any(feature = "esp32", test))]
+const PACKET_CONNECT: u8 = 0x10;
+#[cfg(feature = "esp32")]
+const PACKET_CONNACK: u8 = 0x20;
+#[cfg(feature = "esp32")]
+const PACKET_PUBLISH: u8 = 0x30;
+#[cfg(feature = "esp32")]
+const PACKET_PINGREQ: u8 = 0xc0;
+#[cfg(feature = "esp32")]
+const PACKET_PINGRESP: u8 = 0xd0;
+#[cfg(feature = "esp32")]
+const PACKET_DISCONNECT: u8 = 0xe0;
+#[cfg(feature = "esp32")]
+const RETAIN: u8 = 0x01;
+
+/// publish results as json to an mqtt 3.1.1 broker with qos 0, e.g. for home assistant
+///
+/// the tcp socket is created like the udp one, `stack.get_socket(&mut rx_buffer, &mut tx_buffer)`.
+/// the connection is opened on first send and reopened after an error,
+/// call `poll` in the main loop to keep it alive
+#[cfg(feature = "esp32")]
+pub struct MqttSink<'s, 'n: 's, D: Device> {
+    socket: Socket<'s, 'n, D>,
+    broker: Endpoint,
+    client_id: String,
+    credentials: Option<(String, String)>,
+    // json of all values is published here
+    topic: String,
+    keepalive_secs: u16,
+    timeout_ms: u64,
+    connected: bool,
+    last_sent: Instant,
+}
+
+#[cfg(feature = "esp32")]
+impl<'s, 'n: 's, D: Device> MqttSink<'s, 'n, D> {
+    pub fn new(socket: Socket<'s, 'n, D>, broker: Endpoint, client_id: &str, topic: &str) -> Self {
+        MqttSink {
+            socket,
+            broker,
+            client_id: client_id.to_string(),
+            credentials: None,
+            topic: topic.to_string(),
+            keepalive_secs: 60,
+            timeout_ms: 10_000,
+            connected: false,
+            last_sent: Instant::now(),
+        }
+    }
+
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// the broker drops the connection after 1.5 times of this without any packet
+    pub fn with_keepalive(mut self, secs: u16) -> Self {
+        self.keepalive_secs = secs;
+        self
+    }
+
+    /// max wait for the connack and ping responses, default is 10s
+    pub fn with_timeout(mut self, ms: u64) -> Self {
+        self.timeout_ms = ms;
+        self
+    }
+
+    /// publish retained home assistant discovery configs for co2, temperature and humidity
+    /// under `prefix`, which is `homeassistant` by default in home assistant
+    pub fn publish_discovery(&mut self, prefix: &str) -> Result<(), Error> {
+        let sensors = [
+            ("co2", "carbon_dioxide", "ppm"),
+            ("temp", "temperature", "°C"),
+            ("hum", "humidity", "%"),
+        ];
+        for (key, device_class, unit) in sensors {
+            let topic = format!("{}/sensor/{}_{}/config", prefix, self.client_id, key);
+            let payload = format!(
+                "{{\"name\":\"{key}\",\"unique_id\":\"{id}_{key}\",\"state_topic\":\"{state}\",\"device_class\":\"{device_class}\",\"unit_of_measurement\":\"{unit}\",\"value_template\":\"{{{{ value_json.{key} }}}}\",\"device\":{{\"identifiers\":[\"{id}\"],\"name\":\"{id}\"}}}}",
+                key = key,
+                id = self.client_id,
+                state = self.topic,
+                device_class = device_class,
+                unit = unit,
+            );
+            self.publish(&topic, payload.as_bytes(), true)?;
+        }
+        Ok(())
+    }
+
+    /// publish `payload` to `topic`, connect first if needed
+    pub fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<(), Error> {
+        self.ensure_connected()?;
+        let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+        push_str(&mut body, topic);
+        body.extend_from_slice(payload);
+        let flags = if retain { RETAIN } else { 0 };
+        debug!("publish {} bytes to {}", payload.len(), topic);
+        self.write_packet(PACKET_PUBLISH | flags, &body)
+    }
+
+    /// send a ping if nothing was sent for half of the keepalive, call this in the main loop
+    pub fn keepalive(&mut self) -> Result<(), Error> {
+        if !self.connected {
+            return Ok(());
+        }
+        let half = Duration::from_secs(self.keepalive_secs as u64 / 2);
+        if self.last_sent.elapsed() < half {
+            return Ok(());
+        }
+        debug!("send mqtt ping");
+        self.write_packet(PACKET_PINGREQ, &[])?;
+        let mut resp = [0u8; 2];
+        self.read_exact(&mut resp)?;
+        if resp[0] != PACKET_PINGRESP {
+            return self.fail(format!("unexpected mqtt ping response: 0x{:02x}", resp[0]));
+        }
+        Ok(())
+    }
+
+    /// send disconnect and close the connection
+    pub fn disconnect(&mut self) {
+        if self.connected {
+            if let Err(err) = self.write_packet(PACKET_DISCONNECT, &[]) {
+                debug!("send mqtt disconnect error: {:?}", err);
+            }
+        }
+        self.connected = false;
+        self.socket.disconnect();
+    }
+
+    fn ensure_connected(&mut self) -> Result<(), Error> {
+        if self.connected && self.socket.is_connected() {
+            return Ok(());
+        }
+        if self.connected {
+            warn!("mqtt connection lost, reconnect");
+            self.connected = false;
+            self.socket.disconnect();
+        }
+        let addr = blocking_network_stack::ipv4::Ipv4Addr::from(self.broker.ip);
+        info!("connect to mqtt://{}:{}", addr, self.broker.port);
+        self.socket.open(addr.into(), self.broker.port)?;
+
+        let credentials = self
+            .credentials
+            .as_ref()
+            .map(|(username, password)| (username.as_str(), password.as_str()));
+        let body = connect_body(&self.client_id, self.keepalive_secs, credentials);
+        // mark connected so a failed handshake is cleaned up by `fail`
+        self.connected = true;
+        self.write_packet(PACKET_CONNECT, &body)?;
+
+        let mut ack = [0u8; 4];
+        self.read_exact(&mut ack)?;
+        if ack[0] != PACKET_CONNACK || ack[3] != 0 {
+            return self.fail(format!(
+                "mqtt connect refused, packet: 0x{:02x}, code: {}",
+                ack[0], ack[3]
+            ));
+        }
+        info!("mqtt connected");
+        Ok(())
+    }
+
+    fn write_packet(&mut self, header: u8, body: &[u8]) -> Result<(), Error> {
+        let packet = packet(header, body);
+        let result = self
+            .socket
+            .write_all(packet.as_slice())
+            .and_then(|_| self.socket.flush());
+        if let Err(err) = result {
+            return self.fail(format!("mqtt write error: {:?}", err));
+        }
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        let timeout = Duration::from_millis(self.timeout_ms);
+        let started = Instant::now();
+        let mut read = 0;
+        while read < buf.len() {
+            if started.elapsed() > timeout {
+                self.close();
+                return Err(Error::Timeout);
+            }
+            self.socket.work();
+            match self.socket.read_ready() {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => return self.fail(format!("mqtt read error: {:?}", err)),
+            }
+            match self.socket.read(&mut buf[read..]) {
+                Ok(0) => return self.fail("mqtt connection closed".to_string()),
+                Ok(len) => read += len,
+                Err(err) => return self.fail(format!("mqtt read error: {:?}", err)),
+            }
+        }
+        Ok(())
+    }
+
+    // drop the connection, it is opened again on next publish
+    fn fail(&mut self, msg: String) -> Result<(), Error> {
+        self.close();
+        Err(Error::SimpleError(msg))
+    }
+
+    fn close(&mut self) {
+        self.connected = false;
+        self.socket.disconnect();
+    }
+}
+
+#[cfg(feature = "esp32")]
+impl<D: Device> MetricSink for MqttSink<'_, '_, D> {
+    fn send(&mut self, m: &MeasureResult) -> Result<(), Error> {
+        let mut payload = Vec::new();
+        JsonEncoder.encode(m, &mut payload);
+        let topic = self.topic.clone();
+        self.publish(&topic, payload.as_slice(), false)
+    }
+
+    fn poll(&mut self) -> Result<(), Error> {
+        self.keepalive()
+    }
+}
+
+// variable header and payload of connect, the credentials are username and password
+#[cfg(any(feature = "esp32", test))]
+fn connect_body(
+    client_id: &str,
+    keepalive_secs: u16,
+    credentials: Option<(&str, &str)>,
+) -> Vec<u8> {
+    let mut flags = CONNECT_FLAGS;
+    if credentials.is_some() {
+        flags |= USERNAME_FLAG | PASSWORD_FLAG;
+    }
+    let mut body = Vec::new();
+    push_str(&mut body, "MQTT");
+    body.push(PROTOCOL_LEVEL);
+    body.push(flags);
+    body.extend_from_slice(&keepalive_secs.to_be_bytes());
+    push_str(&mut body, client_id);
+    if let Some((username, password)) = credentials {
+        push_str(&mut body, username);
+        push_str(&mut body, password);
+    }
+    body
+}
+
+// fixed header, then the body
+#[cfg(any(feature = "esp32", test))]
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.push(header);
+    push_remaining_len(&mut packet, body.len());
+    packet.extend_from_slice(body);
+    packet
+}
+
+// utf-8 string with u16 length prefix
+#[cfg(any(feature = "esp32", test))]
+fn push_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+// variable length, 7 bits per byte, high bit set if more bytes follow
+#[cfg(any(feature = "esp32", test))]
+fn push_remaining_len(buf: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        buf.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remaining_len(len: usize) -> Vec<u8> {
+        let mut buf = Vec::new();
+        push_remaining_len(&mut buf, len);
+        buf
+    }
+
+    #[test]
+    fn remaining_len_boundaries() {
+        assert_eq!(remaining_len(0), vec![0x00]);
+        assert_eq!(remaining_len(127), vec![0x7f]);
+        assert_eq!(remaining_len(128), vec![0x80, 0x01]);
+        assert_eq!(remaining_len(16383), vec![0xff, 0x7f]);
+        assert_eq!(remaining_len(16384), vec![0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn connect_packet_bytes() {
+        let body = connect_body("co2", 60, None);
+        let expected = [
+            // connect, 15 bytes follow
+            &[0x10, 0x0f][..],
+            // protocol name, level 4, clean session, keepalive 60s
+            &[0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00, 0x3c],
+            // client id
+            &[0x00, 0x03, b'c', b'o', b'2'],
+        ]
+        .concat();
+        assert_eq!(packet(PACKET_CONNECT, &body), expected);
+    }
+
+    #[test]
+    fn connect_packet_with_credentials() {
+        let body = connect_body("co2", 60, Some(("user", "pw")));
+        let expected = [
+            &[0x10, 0x19][..],
+            &[0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0xc2, 0x00, 0x3c],
+            &[0x00, 0x03, b'c', b'o', b'2'],
+            &[0x00, 0x04, b'u', b's', b'e', b'r'],
+            &[0x00, 0x02, b'p', b'w'],
+        ]
+        .concat();
+        assert_eq!(packet(PACKET_CONNECT, &body), expected);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::error::Error;
+use alloc::format;
+use blocking_network_stack::UdpSocket;
+use esp_hal::delay::Delay;
+use esp_hal::time::Instant;
+use log::{debug, info, warn};
+use smoltcp::phy::Device;
+
+const NTP_PORT: u16 = 123;
+const NTP_PACKET_LEN: usize = 48;
+// seconds from 1900-01-01 to 1970-01-01
+const NTP_UNIX_EPOCH_DIFF: u64 = 2_208_988_800;
+const SYNC_ATTEMPTS: u32 = 2;
+const RESPONSE_TIMEOUT_MS: u32 = 2_000;
+const POLL_INTERVAL_MS: u32 = 100;
+
+/// wall clock from ntp, `Instant` since boot plus the unix time at boot
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    // unix time in ms when `Instant` is 0
+    boot_unix_ms: u64,
+}
+
+impl Clock {
+    /// unix time in ms
+    pub fn now_ms(&self) -> u64 {
+        self.boot_unix_ms + Instant::now().duration_since_epoch().as_millis()
+    }
+
+    /// unix time in ms of an instant
+    pub fn unix_ms(&self, instant: Instant) -> u64 {
+        self.boot_unix_ms + instant.duration_since_epoch().as_millis()
+    }
+}
+
+/// get unix time from sntp `server`, retry once if there is no response.
+/// the socket should be bound already, the response comes back to its port
+pub fn sync<D: Device>(socket: &mut UdpSocket<D>, server: [u8; 4]) -> Result<Clock, Error> {
+    let addr = blocking_network_stack::ipv4::Ipv4Addr::from(server);
+    let mut attempt = 1;
+    loop {
+        match request(socket, addr) {
+            Ok(clock) => {
+                info!("ntp synced, unix time: {}ms", clock.now_ms());
+                return Ok(clock);
+            }
+            Err(err) if attempt >= SYNC_ATTEMPTS => return Err(err),
+            Err(err) => {
+                warn!("ntp sync error: {:?}, attempt {}", err, attempt);
+            }
+        }
+        attempt += 1;
+    }
+}
+
+fn request<D: Device>(
+    socket: &mut UdpSocket<D>,
+    addr: blocking_network_stack::ipv4::Ipv4Addr,
+) -> Result<Clock, Error> {
+    debug!("send ntp request to udp://{}:{}", addr, NTP_PORT);
+    let mut packet = [0u8; NTP_PACKET_LEN];
+    // li: 0, version: 3, mode: 3 (client)
+    packet[0] = 0x1b;
+    let sent_at = Instant::now();
+    socket.send(addr.into(), NTP_PORT, &packet)?;
+
+    let delay = Delay::new();
+    let mut waited = 0;
+    let mut buf = [0u8; NTP_PACKET_LEN];
+    loop {
+        socket.work();
+        if let Ok((len, _, port)) = socket.receive(&mut buf) {
+            if port == NTP_PORT && len >= NTP_PACKET_LEN {
+                break;
+            }
+        }
+        if waited >= RESPONSE_TIMEOUT_MS {
+            return Err(Error::Timeout);
+        }
+        delay.delay_millis(POLL_INTERVAL_MS);
+        waited += POLL_INTERVAL_MS;
+    }
+    let received_at = Instant::now();
+
+    // transmit timestamp, seconds and fraction since 1900
+    let seconds = u32::from_be_bytes([buf[40], buf[41], buf[42], buf[43]]) as u64;
+    let fraction = u32::from_be_bytes([buf[44], buf[45], buf[46], buf[47]]) as u64;
+    if seconds < NTP_UNIX_EPOCH_DIFF {
+        return Err(Error::SimpleError(format!(
+            "invalid ntp timestamp: {}",
+            seconds
+        )));
+    }
+    let server_unix_ms = (seconds - NTP_UNIX_EPOCH_DIFF) * 1000 + ((fraction * 1000) >> 32);
+    // assume the response takes half of the round trip
+    let round_trip_ms = (received_at - sent_at).as_millis();
+    let now_ms = server_unix_ms + round_trip_ms / 2;
+    let since_boot_ms = received_at.duration_since_epoch().as_millis();
+    Ok(Clock {
+        boot_unix_ms: now_ms.saturating_sub(since_boot_ms),
+    })
+}
@@ -0,0 +1,336 @@
+use crate::config;
+use crate::error::Error;
+use crate::net::{parse_ip, Endpoint};
+#[cfg(feature = "esp32")]
+use crate::scd41::crc;
+#[cfg(any(feature = "esp32", test))]
+use alloc::format;
+use alloc::string::{String, ToString};
+#[cfg(any(feature = "esp32", test))]
+use alloc::vec::Vec;
+#[cfg(feature = "esp32")]
+use embedded_storage::{ReadStorage, Storage};
+#[cfg(feature = "esp32")]
+use esp_storage::FlashStorage;
+#[cfg(feature = "esp32")]
+use log::{debug, info, warn};
+
+#[cfg(feature = "esp32")]
+const PARTITION_TABLE_OFFSET: u32 = 0x8000;
+#[cfg(feature = "esp32")]
+const PARTITION_ENTRY_LEN: usize = 32;
+#[cfg(feature = "esp32")]
+const PARTITION_MAX_ENTRIES: u32 = 95;
+#[cfg(feature = "esp32")]
+const PARTITION_MAGIC: [u8; 2] = [0xaa, 0x50];
+// type data, subtype nvs
+#[cfg(feature = "esp32")]
+const NVS_TYPE: u8 = 0x01;
+#[cfg(feature = "esp32")]
+const NVS_SUBTYPE: u8 = 0x02;
+
+#[cfg(feature = "esp32")]
+const RECORD_MAGIC: [u8; 4] = *b"CO2C";
+// magic | payload len(u16) | crc(u8)
+#[cfg(feature = "esp32")]
+const HEADER_LEN: usize = 7;
+#[cfg(feature = "esp32")]
+const MAX_PAYLOAD_LEN: usize = 512;
+
+/// runtime config, stored at the start of the nvs partition
+///
+/// NOTE: the partition is used as raw flash, not in the esp-idf nvs format
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub ssid: String,
+    pub password: String,
+    pub metric_server: String,
+    pub metric_port: u16,
+    pub measure_interval_ms: u32,
+    pub full_refresh_every: u32,
+}
+
+impl Default for Config {
+    /// compile-time values from `config.rs`
+    fn default() -> Self {
+        Config {
+            ssid: config::SSID.to_string(),
+            password: config::PASSWORD.to_string(),
+            metric_server: config::METRIC_SERVER.to_string(),
+            metric_port: config::METRIC_PORT,
+            measure_interval_ms: config::MEASURE_INTERVAL_MS,
+            full_refresh_every: config::FULL_REFRESH_EVERY,
+        }
+    }
+}
+
+impl Config {
+    /// load config from flash, fallback to the compiled defaults if
+    /// the partition is missing or nothing valid is saved, e.g. a bad metric server
+    #[cfg(feature = "esp32")]
+    pub fn load() -> Config {
+        let mut flash = FlashStorage::new();
+        match Self::read(&mut flash) {
+            Ok(Some(cfg)) => {
+                info!("config loaded from nvs");
+                cfg
+            }
+            Ok(None) => {
+                info!("no config in nvs, use default");
+                Config::default()
+            }
+            Err(err) => {
+                warn!("load config error: {:?}, use default", err);
+                Config::default()
+            }
+        }
+    }
+
+    /// write config to flash, it is used after next `load`
+    #[cfg(feature = "esp32")]
+    pub fn save(&self) -> Result<(), Error> {
+        let mut flash = FlashStorage::new();
+        let Some(offset) = find_nvs_partition(&mut flash)? else {
+            return Err(Error::SimpleError("nvs partition not found".to_string()));
+        };
+        let payload = self.encode()?;
+        let mut data = Vec::with_capacity(HEADER_LEN + payload.len());
+        data.extend_from_slice(&RECORD_MAGIC);
+        data.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        data.push(crc(&payload));
+        data.extend_from_slice(&payload);
+        flash
+            .write(offset, &data)
+            .map_err(|err| Error::SimpleError(format!("write flash error: {:?}", err)))?;
+        info!("config saved to nvs at 0x{:x}", offset);
+        Ok(())
+    }
+
+    /// erase the saved config, the compiled defaults are used after next `load`
+    #[cfg(feature = "esp32")]
+    pub fn erase() -> Result<(), Error> {
+        let mut flash = FlashStorage::new();
+        let Some(offset) = find_nvs_partition(&mut flash)? else {
+            return Err(Error::SimpleError("nvs partition not found".to_string()));
+        };
+        // erased flash, the magic no longer matches
+        flash
+            .write(offset, &[0xff; HEADER_LEN])
+            .map_err(|err| Error::SimpleError(format!("write flash error: {:?}", err)))?;
+        info!("config erased from nvs at 0x{:x}", offset);
+        Ok(())
+    }
+
+    /// address of the metric server, the compiled default may still be invalid
+    pub fn metric_endpoint(&self) -> Result<Endpoint, Error> {
+        Ok(Endpoint {
+            ip: parse_ip(&self.metric_server)?,
+            port: self.metric_port,
+        })
+    }
+
+    #[cfg(feature = "esp32")]
+    fn read(flash: &mut FlashStorage) -> Result<Option<Config>, Error> {
+        let Some(offset) = find_nvs_partition(flash)? else {
+            warn!("nvs partition not found");
+            return Ok(None);
+        };
+        let mut header = [0u8; HEADER_LEN];
+        read_flash(flash, offset, &mut header)?;
+        if header[..4] != RECORD_MAGIC {
+            return Ok(None);
+        }
+        let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if len > MAX_PAYLOAD_LEN {
+            return Err(Error::SimpleError(format!("invalid config len: {}", len)));
+        }
+        let mut payload = alloc::vec![0u8; len];
+        read_flash(flash, offset + HEADER_LEN as u32, &mut payload)?;
+        let expected = crc(&payload);
+        if expected != header[6] {
+            return Err(Error::CrcMismatch {
+                expected,
+                got: header[6],
+            });
+        }
+        Self::decode(&payload).map(Some)
+    }
+
+    // ssid | password | metric server, each is u8 len + bytes,
+    // then port(u16) | measure interval(u32) | full refresh every(u32)
+    #[cfg(any(feature = "esp32", test))]
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        // a bad address would fail every boot after `load`
+        parse_ip(&self.metric_server)?;
+        let mut buf = Vec::new();
+        for field in [&self.ssid, &self.password, &self.metric_server] {
+            if field.len() > u8::MAX as usize {
+                return Err(Error::SimpleError(format!(
+                    "config field too long: {}",
+                    field
+                )));
+            }
+            buf.push(field.len() as u8);
+            buf.extend_from_slice(field.as_bytes());
+        }
+        buf.extend_from_slice(&self.metric_port.to_be_bytes());
+        buf.extend_from_slice(&self.measure_interval_ms.to_be_bytes());
+        buf.extend_from_slice(&self.full_refresh_every.to_be_bytes());
+        Ok(buf)
+    }
+
+    #[cfg(any(feature = "esp32", test))]
+    fn decode(data: &[u8]) -> Result<Config, Error> {
+        let mut rest = data;
+        let mut next_str = || -> Result<String, Error> {
+            let (&len, tail) = rest
+                .split_first()
+                .ok_or(Error::SimpleError("config too short".to_string()))?;
+            let len = len as usize;
+            if tail.len() < len {
+                return Err(Error::SimpleError("config too short".to_string()));
+            }
+            let s = core::str::from_utf8(&tail[..len])
+                .map_err(|_| Error::SimpleError("invalid utf8 in config".to_string()))?;
+            rest = &tail[len..];
+            Ok(s.to_string())
+        };
+        let ssid = next_str()?;
+        let password = next_str()?;
+        let metric_server = next_str()?;
+        parse_ip(&metric_server)?;
+        let defaults = Config::default();
+        let (metric_port, measure_interval_ms, full_refresh_every) = match rest {
+            [p0, p1, i0, i1, i2, i3, f0, f1, f2, f3] => (
+                u16::from_be_bytes([*p0, *p1]),
+                u32::from_be_bytes([*i0, *i1, *i2, *i3]),
+                u32::from_be_bytes([*f0, *f1, *f2, *f3]),
+            ),
+            // saved before the intervals were added
+            [p0, p1] => (
+                u16::from_be_bytes([*p0, *p1]),
+                defaults.measure_interval_ms,
+                defaults.full_refresh_every,
+            ),
+            _ => return Err(Error::SimpleError("invalid config len".to_string())),
+        };
+        Ok(Config {
+            ssid,
+            password,
+            metric_server,
+            metric_port,
+            measure_interval_ms,
+            full_refresh_every,
+        })
+    }
+}
+
+#[cfg(feature = "esp32")]
+fn read_flash(flash: &mut FlashStorage, offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+    flash
+        .read(offset, buf)
+        .map_err(|err| Error::SimpleError(format!("read flash error: {:?}", err)))
+}
+
+// scan the partition table for the first nvs partition, return its offset
+#[cfg(feature = "esp32")]
+fn find_nvs_partition(flash: &mut FlashStorage) -> Result<Option<u32>, Error> {
+    let mut entry = [0u8; PARTITION_ENTRY_LEN];
+    for idx in 0..PARTITION_MAX_ENTRIES {
+        let offset = PARTITION_TABLE_OFFSET + idx * PARTITION_ENTRY_LEN as u32;
+        read_flash(flash, offset, &mut entry)?;
+        // the table ends with the md5 entry or erased flash
+        if entry[..2] != PARTITION_MAGIC {
+            break;
+        }
+        if entry[2] == NVS_TYPE && entry[3] == NVS_SUBTYPE {
+            let offset = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+            debug!("found nvs partition at 0x{:x}", offset);
+            return Ok(Some(offset));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            ssid: "home".to_string(),
+            password: "secret".to_string(),
+            metric_server: "192.168.1.2".to_string(),
+            metric_port: 8125,
+            measure_interval_ms: 30_000,
+            full_refresh_every: 12,
+        }
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let cfg = config();
+        let data = cfg.encode().unwrap();
+        assert_eq!(data.len(), 1 + 4 + 1 + 6 + 1 + 11 + 2 + 4 + 4);
+        assert_eq!(Config::decode(&data).unwrap(), cfg);
+    }
+
+    #[test]
+    fn decode_legacy_port_only_tail() {
+        let cfg = config();
+        let mut data = cfg.encode().unwrap();
+        // saved before the intervals were added
+        data.truncate(data.len() - 8);
+        let defaults = Config::default();
+        let decoded = Config::decode(&data).unwrap();
+        assert_eq!(
+            decoded,
+            Config {
+                measure_interval_ms: defaults.measure_interval_ms,
+                full_refresh_every: defaults.full_refresh_every,
+                ..cfg
+            }
+        );
+    }
+
+    #[test]
+    fn decode_rejects_truncated_input() {
+        let data = config().encode().unwrap();
+        for len in [0, 3, 5, data.len() - 9, data.len() - 1] {
+            assert!(Config::decode(&data[..len]).is_err(), "len {}", len);
+        }
+    }
+
+    #[test]
+    fn decode_rejects_invalid_utf8() {
+        let mut data = config().encode().unwrap();
+        // first byte of the ssid
+        data[1] = 0xff;
+        assert!(matches!(
+            Config::decode(&data),
+            Err(Error::SimpleError(msg)) if msg.contains("utf8")
+        ));
+    }
+
+    #[test]
+    fn bad_metric_server_is_rejected() {
+        let cfg = Config {
+            metric_server: "192.168.1".to_string(),
+            ..config()
+        };
+        assert!(cfg.encode().is_err());
+        assert!(cfg.metric_endpoint().is_err());
+
+        // written by an older firmware without the check
+        let mut data = Vec::new();
+        for field in ["home", "secret", "192.168.1.256"] {
+            data.push(field.len() as u8);
+            data.extend_from_slice(field.as_bytes());
+        }
+        data.extend_from_slice(&8125u16.to_be_bytes());
+        assert!(matches!(
+            Config::decode(&data),
+            Err(Error::SimpleError(msg)) if msg.contains("invalid ip")
+        ));
+    }
+}
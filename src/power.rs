@@ -0,0 +1,69 @@
+use esp_hal::analog::adc::{Adc, AdcChannel, AdcConfig, AdcPin, Attenuation};
+use esp_hal::gpio::AnalogPin;
+use esp_hal::peripherals::ADC1;
+use esp_hal::Blocking;
+use log::{debug, warn};
+
+// pin voltage of the max raw value with 11dB attenuation, the adc is not
+// calibrated, expect an error of about 100mV
+const FULL_SCALE_MV: u32 = 3300;
+const ADC_MAX: u32 = 4095;
+// samples averaged per reading
+const SAMPLES: u32 = 8;
+
+/// battery voltage through a resistor divider on an adc1 pin,
+/// adc2 can not be used while wifi is on
+pub struct Battery<'a, P> {
+    adc: Adc<'a, ADC1<'a>, Blocking>,
+    pin: AdcPin<P, ADC1<'a>>,
+    // battery voltage / pin voltage
+    divider: u32,
+}
+
+impl<'a, P> Battery<'a, P>
+where
+    P: AdcChannel + AnalogPin,
+{
+    /// `divider` is the ratio of the battery voltage to the pin voltage,
+    /// e.g. 2 for two equal resistors
+    pub fn new(adc1: ADC1<'a>, pin: P, divider: u32) -> Self {
+        let mut config = AdcConfig::new();
+        let pin = config.enable_pin(pin, Attenuation::_11dB);
+        Battery {
+            adc: Adc::new(adc1, config),
+            pin,
+            divider,
+        }
+    }
+
+    /// battery voltage in mV, averaged over a few samples
+    pub fn battery_millivolts(&mut self) -> u32 {
+        let mut sum = 0;
+        for _ in 0..SAMPLES {
+            sum += self.read_raw() as u32;
+        }
+        let raw = sum / SAMPLES;
+        let mv = raw * FULL_SCALE_MV / ADC_MAX * self.divider;
+        debug!("battery raw: {}, {}mV", raw, mv);
+        mv
+    }
+
+    /// whether the battery voltage is below `threshold_mv`
+    pub fn is_below(&mut self, threshold_mv: u32) -> bool {
+        let mv = self.battery_millivolts();
+        if mv < threshold_mv {
+            warn!("low battery: {}mV, below {}mV", mv, threshold_mv);
+            return true;
+        }
+        false
+    }
+
+    fn read_raw(&mut self) -> u16 {
+        // the conversion is started by the first call and polled until done
+        loop {
+            if let Ok(value) = self.adc.read_oneshot(&mut self.pin) {
+                return value;
+            }
+        }
+    }
+}
@@ -1,166 +1,864 @@
+use crate::error::Error;
+use alloc::format;
+#[cfg(feature = "esp32")]
+use alloc::string::ToString;
+#[cfg(feature = "esp32")]
 use alloc::vec;
+#[cfg(feature = "esp32")]
 use alloc::vec::Vec;
-use core::cell::RefCell;
+#[cfg(feature = "esp32")]
+use core::cell::{Cell, RefCell};
+#[cfg(feature = "esp32")]
+use core::task::Poll;
+#[cfg(feature = "esp32")]
 use esp_hal::delay::Delay;
-use esp_hal::i2c::master::{Error, I2c};
+#[cfg(feature = "esp32")]
+use esp_hal::gpio::Output;
+#[cfg(feature = "esp32")]
+use esp_hal::i2c::master::I2c;
+#[cfg(feature = "esp32")]
+use esp_hal::time::{Duration, Instant};
+#[cfg(feature = "esp32")]
 use esp_hal::Blocking;
-use log::debug;
+#[cfg(feature = "esp32")]
+use log::{debug, warn};
+
+#[cfg(feature = "async")]
+pub mod asynch;
 
 // ref: https://sensirion.com/media/documents/48C4B7FB/67FE0194/CD_DS_SCD4x_Datasheet_D1.pdf
-// read measurement, call start_periodic_measurement before call this
-pub const CMD_READ_MEASUREMENT: u16 = 0xec05;
-// start periodic measurement
-pub const CMD_START_PERIODIC_MEASUREMENT: u16 = 0x21b1;
-// stop periodic measurement
-pub const CMD_STOP_PERIODIC_MEASUREMENT: u16 = 0x3f86;
-// measure oneshot, delay 5s before reading result
-pub const CMD_ONESHOT_MEASUREMENT: u16 = 0x219d;
-// start low power periodic measurement
-pub const CMD_START_LOW_POWER_PERIODIC_MEASUREMENT: u16 = 0x21ac;
-// wait data ready
-pub const CMD_GET_DATA_READY_STATUS: u16 = 0xe4b8;
-// get temperature offset
-pub const CMD_GET_TEMPERATURE_OFFSET: u16 = 0x2318;
-// set temperature offset
-pub const CMD_SET_TEMPERATURE_OFFSET: u16 = 0x241d;
-// wake up sensor
-pub const CMD_WAKEUP: u16 = 0x36f6;
-// persist settings
-pub const CMD_PERSIST_SETTINGS: u16 = 0x3615;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scd41Command {
+    // read measurement, call start_periodic_measurement before call this
+    ReadMeasurement,
+    // start periodic measurement
+    StartPeriodicMeasurement,
+    // stop periodic measurement
+    StopPeriodicMeasurement,
+    // measure oneshot, delay 5s before reading result
+    OneshotMeasurement,
+    // measure temperature and humidity oneshot, delay 50ms before reading result
+    OneshotMeasurementRht,
+    // start low power periodic measurement
+    StartLowPowerPeriodicMeasurement,
+    // wait data ready
+    GetDataReadyStatus,
+    // get temperature offset
+    GetTemperatureOffset,
+    // set temperature offset
+    SetTemperatureOffset,
+    // wake up sensor
+    Wakeup,
+    // power down sensor, wake up before sending other commands
+    PowerDown,
+    // persist settings
+    PersistSettings,
+    // set automatic self calibration enabled
+    SetAutomaticSelfCalibrationEnabled,
+    // get automatic self calibration enabled
+    GetAutomaticSelfCalibrationEnabled,
+    // set ambient pressure, can be sent during periodic measurement
+    SetAmbientPressure,
+    // set sensor altitude, call stop_periodic_measurement before call this
+    SetSensorAltitude,
+    // get serial number
+    GetSerialNumber,
+    // perform self test
+    PerformSelfTest,
+    // reset settings to factory defaults
+    PerformFactoryReset,
+    // forced recalibration, call stop_periodic_measurement before call this
+    PerformForcedRecalibration,
+    // get sensor variant, call stop_periodic_measurement before call this
+    GetSensorVariant,
+}
+
+impl Scd41Command {
+    pub fn opcode(&self) -> u16 {
+        match self {
+            Scd41Command::ReadMeasurement => 0xec05,
+            Scd41Command::StartPeriodicMeasurement => 0x21b1,
+            Scd41Command::StopPeriodicMeasurement => 0x3f86,
+            Scd41Command::OneshotMeasurement => 0x219d,
+            Scd41Command::OneshotMeasurementRht => 0x2196,
+            Scd41Command::StartLowPowerPeriodicMeasurement => 0x21ac,
+            Scd41Command::GetDataReadyStatus => 0xe4b8,
+            Scd41Command::GetTemperatureOffset => 0x2318,
+            Scd41Command::SetTemperatureOffset => 0x241d,
+            Scd41Command::Wakeup => 0x36f6,
+            Scd41Command::PowerDown => 0x36e0,
+            Scd41Command::PersistSettings => 0x3615,
+            Scd41Command::SetAutomaticSelfCalibrationEnabled => 0x2416,
+            Scd41Command::GetAutomaticSelfCalibrationEnabled => 0x2313,
+            Scd41Command::SetAmbientPressure => 0xe000,
+            Scd41Command::SetSensorAltitude => 0x2427,
+            Scd41Command::GetSerialNumber => 0x3682,
+            Scd41Command::PerformSelfTest => 0x3639,
+            Scd41Command::PerformFactoryReset => 0x3632,
+            Scd41Command::PerformForcedRecalibration => 0x362f,
+            Scd41Command::GetSensorVariant => 0x202f,
+        }
+    }
+
+    /// bytes to read after the command, each word is followed by a crc byte
+    pub fn response_len(&self) -> usize {
+        match self {
+            Scd41Command::ReadMeasurement | Scd41Command::GetSerialNumber => 9,
+            Scd41Command::GetDataReadyStatus
+            | Scd41Command::GetTemperatureOffset
+            | Scd41Command::GetAutomaticSelfCalibrationEnabled
+            | Scd41Command::PerformSelfTest
+            | Scd41Command::PerformForcedRecalibration
+            | Scd41Command::GetSensorVariant => 3,
+            _ => 0,
+        }
+    }
+
+    /// whether the command takes a word argument
+    pub fn has_arg(&self) -> bool {
+        matches!(
+            self,
+            Scd41Command::SetTemperatureOffset
+                | Scd41Command::SetAutomaticSelfCalibrationEnabled
+                | Scd41Command::SetAmbientPressure
+                | Scd41Command::SetSensorAltitude
+                | Scd41Command::PerformForcedRecalibration
+        )
+    }
+
+    /// whether the command is only available on scd41, not on scd40
+    pub fn scd41_only(&self) -> bool {
+        matches!(
+            self,
+            Scd41Command::OneshotMeasurement
+                | Scd41Command::OneshotMeasurementRht
+                | Scd41Command::PowerDown
+                | Scd41Command::Wakeup
+        )
+    }
+
+    /// time in ms to wait before the sensor accepts the next command or the response is ready
+    pub fn delay_ms(&self) -> u32 {
+        match self {
+            Scd41Command::StartPeriodicMeasurement
+            | Scd41Command::StopPeriodicMeasurement
+            | Scd41Command::StartLowPowerPeriodicMeasurement => 500,
+            Scd41Command::OneshotMeasurement => 5000,
+            Scd41Command::OneshotMeasurementRht => 50,
+            Scd41Command::Wakeup => 30,
+            Scd41Command::PersistSettings => 600,
+            Scd41Command::PerformSelfTest => 10000,
+            Scd41Command::PerformFactoryReset => 1200,
+            Scd41Command::PerformForcedRecalibration => 400,
+            _ => 1,
+        }
+    }
+}
+
+/// measurement state of the sensor, see the state machine in the datasheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Idle,
+    Periodic,
+    LowPower,
+    // single shot measurement sent, waiting for the result to be read
+    SingleShot,
+}
+
+impl Mode {
+    /// whether the sensor accepts `cmd` in this mode
+    ///
+    /// during periodic measurement only reading results, pressure compensation
+    /// and stopping are allowed, after a single shot the result must be read first
+    pub fn allows(&self, cmd: Scd41Command) -> bool {
+        match self {
+            Mode::Idle => true,
+            Mode::Periodic | Mode::LowPower => matches!(
+                cmd,
+                Scd41Command::ReadMeasurement
+                    | Scd41Command::GetDataReadyStatus
+                    | Scd41Command::SetAmbientPressure
+                    | Scd41Command::StopPeriodicMeasurement
+            ),
+            Mode::SingleShot => matches!(
+                cmd,
+                Scd41Command::ReadMeasurement | Scd41Command::GetDataReadyStatus
+            ),
+        }
+    }
+
+    /// whether periodic measurement is running
+    pub fn is_periodic(&self) -> bool {
+        matches!(self, Mode::Periodic | Mode::LowPower)
+    }
+}
+
 // SCD41 I2C address
+#[cfg(feature = "esp32")]
 const SCD41_ADDRESS: u8 = 0x62;
+// max wait time for data ready, a bit longer than the sample interval of each mode
+#[cfg(feature = "esp32")]
+const PERIODIC_WAIT_READY_TIMEOUT_MS: u32 = 10_000;
+#[cfg(feature = "esp32")]
+const LOW_POWER_WAIT_READY_TIMEOUT_MS: u32 = 35_000;
+#[cfg(feature = "esp32")]
+const DEFAULT_WAIT_READY_TIMEOUT_MS: u32 = 60_000;
+// time between two results of each periodic mode
+#[cfg(feature = "esp32")]
+const PERIODIC_SAMPLE_INTERVAL_MS: u32 = 5_000;
+#[cfg(feature = "esp32")]
+const LOW_POWER_SAMPLE_INTERVAL_MS: u32 = 30_000;
+// max time from power up to idle state
+#[cfg(feature = "esp32")]
+const POWER_UP_DELAY_MS: u32 = 30;
+// min time between two data ready checks of `poll`
+#[cfg(feature = "esp32")]
+const POLL_CHECK_INTERVAL_MS: u64 = 1_000;
+// recommended range of the temperature offset in celsius
+#[cfg(feature = "esp32")]
+const TEMPERATURE_OFFSET_MIN: f32 = 0.0;
+#[cfg(feature = "esp32")]
+const TEMPERATURE_OFFSET_MAX: f32 = 20.0;
+
+// progress of `SCD41::poll`, it goes back to idle once a result is returned
+#[cfg(feature = "esp32")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum PollState {
+    #[default]
+    Idle,
+    // waiting since the first instant, data ready was last checked at the second one
+    WaitingReady(Instant, Option<Instant>),
+    // data is ready, read it on next poll
+    Reading,
+}
 
-#[derive(PartialEq, Default)]
+/// sensor of the scd4x family, scd40 has no single shot and power commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scd4xModel {
+    Scd40,
+    #[default]
+    Scd41,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct MeasureResult {
     pub co2_ppm: u16,
     pub temp: f32,
     pub hum: f32,
 }
 
+impl MeasureResult {
+    /// length of the udp wire format, see `to_bytes`
+    pub const WIRE_LEN: usize = 10;
+
+    /// udp wire format, big endian `temp(f32) | hum(f32) | co2_ppm(u16)`
+    pub fn to_bytes(&self) -> [u8; Self::WIRE_LEN] {
+        let mut data = [0u8; Self::WIRE_LEN];
+        data[0..4].copy_from_slice(&self.temp.to_be_bytes());
+        data[4..8].copy_from_slice(&self.hum.to_be_bytes());
+        data[8..10].copy_from_slice(&self.co2_ppm.to_be_bytes());
+        data
+    }
+
+    /// parse the udp wire format of `to_bytes`, trailing bytes are ignored,
+    /// e.g. the 4 byte age appended by `BinaryEncoder::encode_with_age`.
+    /// `metric_server/server.py` takes both the 10 and the 14 byte form
+    pub fn from_bytes(data: &[u8]) -> Result<MeasureResult, Error> {
+        if data.len() < Self::WIRE_LEN {
+            return Err(Error::SimpleError(format!(
+                "expected at least {} bytes, got {}",
+                Self::WIRE_LEN,
+                data.len()
+            )));
+        }
+        let word = |i: usize| [data[i], data[i + 1], data[i + 2], data[i + 3]];
+        Ok(MeasureResult {
+            temp: f32::from_be_bytes(word(0)),
+            hum: f32::from_be_bytes(word(4)),
+            co2_ppm: u16::from_be_bytes([data[8], data[9]]),
+        })
+    }
+
+    /// check if the result is in the range the sensor can actually measure
+    pub fn is_plausible(&self) -> bool {
+        (1..=40000).contains(&self.co2_ppm)
+            && (-10.0..=60.0).contains(&self.temp)
+            && (0.0..=100.0).contains(&self.hum)
+    }
+
+    /// check if any value changed more than its delta, e.g. to skip redraws on sensor noise
+    pub fn differs_significantly(
+        &self,
+        other: &MeasureResult,
+        co2_delta: u16,
+        temp_delta: f32,
+        hum_delta: f32,
+    ) -> bool {
+        self.co2_ppm.abs_diff(other.co2_ppm) > co2_delta
+            || (self.temp - other.temp).abs() > temp_delta
+            || (self.hum - other.hum).abs() > hum_delta
+    }
+
+    /// temperature in fahrenheit, `temp` is in celsius as the sensor reports
+    pub fn temp_fahrenheit(&self) -> f32 {
+        self.temp * 9.0 / 5.0 + 32.0
+    }
+
+    /// temperature in the given unit
+    pub fn temp_in(&self, unit: TempUnit) -> f32 {
+        match unit {
+            TempUnit::Celsius => self.temp,
+            TempUnit::Fahrenheit => self.temp_fahrenheit(),
+        }
+    }
+
+    /// replace temperature and humidity with a dedicated sensor's, e.g. an sht4x,
+    /// which is not affected by the self heating of the scd41. co2 is kept.
+    ///
+    /// this is for display only, the scd41 does not take an external humidity and
+    /// still compensates co2 by its own, so co2 accuracy still depends on a proper
+    /// `set_temperature_offset`, which can be chosen by comparing with the external reading
+    pub fn with_external_rht(self, temp: f32, hum: f32) -> Self {
+        MeasureResult {
+            co2_ppm: self.co2_ppm,
+            temp,
+            hum,
+        }
+    }
+}
+
+/// unit to show temperature in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TempUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl TempUnit {
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            TempUnit::Celsius => "C",
+            TempUnit::Fahrenheit => "F",
+        }
+    }
+}
+
+#[cfg(feature = "esp32")]
 pub struct SCD41<'a> {
     i2c: RefCell<I2c<'a, Blocking>>,
+    // enable pin of the sensor power rail, high for on
+    power: Option<Output<'a>>,
+    model: Scd4xModel,
     delay: Delay,
-    started: bool,
+    // set by the commands in `&self` methods too
+    mode: Cell<Mode>,
+    wait_ready_timeout_ms: u32,
+    sample_interval_ms: u32,
+    poll_state: PollState,
 }
 
+#[cfg(feature = "esp32")]
 impl<'a> SCD41<'a> {
     pub fn new(i2c: I2c<'a, Blocking>) -> Self {
         SCD41 {
             i2c: RefCell::new(i2c),
+            power: None,
+            model: Scd4xModel::default(),
             delay: Delay::new(),
-            started: false,
+            mode: Cell::new(Mode::Idle),
+            wait_ready_timeout_ms: DEFAULT_WAIT_READY_TIMEOUT_MS,
+            sample_interval_ms: 0,
+            poll_state: PollState::Idle,
         }
     }
 
-    /// get measurement results from sensor
+    /// create sensor of `model`, commands it does not support return an error.
+    /// `new` assumes scd41, use `detect_model` to read it from the sensor
+    pub fn new_with_model(i2c: I2c<'a, Blocking>, model: Scd4xModel) -> Self {
+        SCD41 {
+            model,
+            ..Self::new(i2c)
+        }
+    }
+
+    pub fn model(&self) -> Scd4xModel {
+        self.model
+    }
+
+    /// current measurement mode, commands not allowed in it return an error
+    pub fn mode(&self) -> Mode {
+        self.mode.get()
+    }
+
+    /// read the model from sensor and use it from now on, sensor must be idle
+    pub fn detect_model(&mut self) -> Result<Scd4xModel, Error> {
+        self.cmd(Scd41Command::GetSensorVariant)?;
+        let variant = self.read_word()?;
+        // bit 15:12 is the variant
+        let model = match variant >> 12 {
+            0b0000 => Scd4xModel::Scd40,
+            0b0001 => Scd4xModel::Scd41,
+            _ => {
+                return Err(Error::SimpleError(format!(
+                    "unknown sensor variant: 0x{:04x}",
+                    variant
+                )));
+            }
+        };
+        debug!("sensor model: {:?}", model);
+        self.model = model;
+        Ok(model)
+    }
+
+    /// create sensor with its power rail controlled by `power`, see `power_on`
+    pub fn new_with_power(i2c: I2c<'a, Blocking>, power: Output<'a>) -> Self {
+        SCD41 {
+            power: Some(power),
+            ..Self::new(i2c)
+        }
+    }
+
+    /// scan the bus of this sensor, see `scan_bus`
+    pub fn scan_bus(&self) -> Vec<u8> {
+        scan_bus(&mut self.i2c.borrow_mut())
+    }
+
+    /// enable the power rail and wait until the sensor is idle,
+    /// do nothing if there is no power pin
+    pub fn power_on(&mut self) -> Result<(), Error> {
+        let Some(power) = self.power.as_mut() else {
+            return Ok(());
+        };
+        power.set_high();
+        self.delay.delay_millis(POWER_UP_DELAY_MS);
+        self.mode.set(Mode::Idle);
+        Ok(())
+    }
+
+    /// cut the power rail, settings not persisted are lost
+    pub fn power_off(&mut self) {
+        if let Some(power) = self.power.as_mut() {
+            power.set_low();
+            self.mode.set(Mode::Idle);
+        }
+    }
+
+    /// get a fresh measurement result, power on and start low power measurement if not started
+    ///
+    /// unlike `measure`, this never returns `Error::NotStarted`
+    pub fn measure_blocking(&mut self) -> Result<MeasureResult, Error> {
+        if !self.mode().is_periodic() {
+            self.power_on()?;
+            self.start_low_power()?;
+        }
+        self.measure_validated()
+    }
+
+    /// get measurement results from sensor, return `Error::NotStarted` if periodic
+    /// measurement is not started, so it is not taken as a reading of 0
     pub fn measure(&self) -> Result<MeasureResult, Error> {
-        if !self.started {
-            return Ok(MeasureResult {
-                co2_ppm: 0,
-                temp: 0.0,
-                hum: 0.0,
-            });
+        if !self.mode().is_periodic() {
+            return Err(Error::NotStarted);
         }
-        self.wait_ready()?;
-        self.cmd(CMD_READ_MEASUREMENT)?;
-        self.delay.delay_millis(1);
-        let data = self.read(9)?;
+        self.wait_ready_timeout(self.wait_ready_timeout_ms)?;
+        let data = self.cmd_read(Scd41Command::ReadMeasurement)?;
         self.parse(data.as_slice())
     }
 
-    /// get measurement results from sensor
+    /// non-blocking version of `measure`, return `Poll::Pending` until the result is read
+    ///
+    /// each call does at most one i2c command: the data ready status is checked
+    /// once per second, then the result is read on the following call.
+    /// call it at least once per second, e.g. next to `stack.work()` in the main loop,
+    /// a new result is ready every 5s, or 30s in low power mode.
+    /// `Error::Timeout` is returned if no data is ready within the wait ready timeout
+    pub fn poll(&mut self) -> Poll<Result<MeasureResult, Error>> {
+        if !self.mode().is_periodic() {
+            self.poll_state = PollState::Idle;
+            return Poll::Ready(Err(Error::NotStarted));
+        }
+        let now = Instant::now();
+        match self.poll_state {
+            PollState::Idle => {
+                self.poll_state = PollState::WaitingReady(now, None);
+                Poll::Pending
+            }
+            PollState::WaitingReady(since, checked) => {
+                let interval = Duration::from_millis(POLL_CHECK_INTERVAL_MS);
+                if checked.is_some_and(|checked| now - checked < interval) {
+                    return Poll::Pending;
+                }
+                match self.data_ready() {
+                    Ok(true) => {
+                        self.poll_state = PollState::Reading;
+                        Poll::Pending
+                    }
+                    Ok(false) => {
+                        let waited = now - since;
+                        if waited > Duration::from_millis(self.wait_ready_timeout_ms as u64) {
+                            warn!(
+                                "wait sensor data ready timeout after {}ms",
+                                waited.as_millis()
+                            );
+                            self.poll_state = PollState::Idle;
+                            return Poll::Ready(Err(Error::Timeout));
+                        }
+                        self.poll_state = PollState::WaitingReady(since, Some(now));
+                        Poll::Pending
+                    }
+                    Err(err) => {
+                        self.poll_state = PollState::Idle;
+                        Poll::Ready(Err(err))
+                    }
+                }
+            }
+            PollState::Reading => {
+                self.poll_state = PollState::Idle;
+                Poll::Ready(
+                    self.cmd_read(Scd41Command::ReadMeasurement)
+                        .and_then(|data| self.parse(data.as_slice())),
+                )
+            }
+        }
+    }
+
+    /// get measurement results from sensor, reject results out of sensor range
+    pub fn measure_validated(&self) -> Result<MeasureResult, Error> {
+        validate(self.measure()?)
+    }
+
+    /// get a fresh result without waiting, return none if no new sample is ready yet
+    ///
+    /// data ready is checked once, so the caller can do other work and try again later,
+    /// e.g. a new sample comes every 30s in low power mode.
+    /// results out of sensor range are rejected like `measure_validated`
+    pub fn measure_if_ready(&self) -> Result<Option<MeasureResult>, Error> {
+        if !self.mode().is_periodic() {
+            return Err(Error::NotStarted);
+        }
+        if !self.data_ready()? {
+            return Ok(None);
+        }
+        let data = self.cmd_read(Scd41Command::ReadMeasurement)?;
+        validate(self.parse(data.as_slice())?).map(Some)
+    }
+
+    /// get measurement results from sensor, takes 5s
     pub fn measure_oneshot(&self) -> Result<MeasureResult, Error> {
-        self.cmd(CMD_ONESHOT_MEASUREMENT)?;
-        self.delay.delay_millis(5000);
-        let data = self.read(9)?;
-        self.parse(data.as_slice())
+        self.cmd(Scd41Command::OneshotMeasurement)?;
+        self.mode.set(Mode::SingleShot);
+        // the result is not returned by the command, read it with read_measurement
+        let data = self.cmd_read(Scd41Command::ReadMeasurement);
+        self.mode.set(Mode::Idle);
+        self.parse(data?.as_slice())
+    }
+
+    /// get temperature and humidity only from sensor, takes about 50ms instead of 5s
+    ///
+    /// co2 is not measured, `co2_ppm` of the result is always 0
+    pub fn measure_oneshot_rht(&self) -> Result<MeasureResult, Error> {
+        self.cmd(Scd41Command::OneshotMeasurementRht)?;
+        self.mode.set(Mode::SingleShot);
+        // result is read with read_measurement, the co2 word is 0
+        let data = self.cmd_read(Scd41Command::ReadMeasurement);
+        self.mode.set(Mode::Idle);
+        let m = self.parse(data?.as_slice())?;
+        Ok(MeasureResult { co2_ppm: 0, ..m })
     }
 
     /// get temperature offset from sensor
     pub fn get_temperature_offset(&self) -> Result<f32, Error> {
-        self.cmd(CMD_GET_TEMPERATURE_OFFSET)?;
-        self.delay.delay_millis(1);
-        let data = self.read(2)?;
-        let offset = 175.0 * u16::from_be_bytes([data[0], data[1]]) as f32 / 65535.0;
-        Ok(offset)
+        self.cmd(Scd41Command::GetTemperatureOffset)?;
+        Ok(temperature_offset_from_word(self.read_word()?))
     }
 
     /// set temperature offset from sensor
     pub fn set_temperature_offset(&self, offset: f32) -> Result<(), Error> {
-        let offset = (offset * 65535.0 / 175.0) as u16;
-        let offset_data = offset.to_be_bytes();
-        let mut data = offset_data.to_vec();
-        let crc = crc(&offset_data);
-        data.push(crc);
-        self.cmd_with_arg(CMD_SET_TEMPERATURE_OFFSET, data)
+        self.cmd_with_word(
+            Scd41Command::SetTemperatureOffset,
+            temperature_offset_word(offset),
+        )
     }
 
-    /// persist settings for sensor
+    /// set and persist the temperature offset so the reported temperature matches `reference_c`,
+    /// return the new offset
+    ///
+    /// sensor must be idle, and should have been running in its enclosure long enough
+    /// for the self-heating to settle. the temperature is taken with `measure_oneshot_rht`
+    pub fn calibrate_temperature_to(&self, reference_c: f32) -> Result<f32, Error> {
+        let current = self.get_temperature_offset()?;
+        let measured = self.measure_oneshot_rht()?.temp;
+        let offset = measured - reference_c + current;
+        if !(TEMPERATURE_OFFSET_MIN..=TEMPERATURE_OFFSET_MAX).contains(&offset) {
+            return Err(Error::SimpleError(format!(
+                "temperature offset {:.2} out of range, measured: {:.2}, reference: {:.2}",
+                offset, measured, reference_c
+            )));
+        }
+        debug!(
+            "temperature offset {:.2} -> {:.2}, measured: {:.2}, reference: {:.2}",
+            current, offset, measured, reference_c
+        );
+        self.set_temperature_offset(offset)?;
+        self.persist_settings()?;
+        Ok(offset)
+    }
+
+    /// enable or disable automatic self calibration, sensor must be idle
+    pub fn set_automatic_self_calibration(&self, enabled: bool) -> Result<(), Error> {
+        self.cmd_with_word(
+            Scd41Command::SetAutomaticSelfCalibrationEnabled,
+            enabled as u16,
+        )
+    }
+
+    /// get automatic self calibration status, sensor must be idle
+    pub fn get_automatic_self_calibration(&self) -> Result<bool, Error> {
+        self.cmd(Scd41Command::GetAutomaticSelfCalibrationEnabled)?;
+        let enabled = self.read_word()?;
+        Ok(enabled != 0)
+    }
+
+    /// set ambient pressure in Pa for pressure compensation
+    ///
+    /// this can be called during periodic measurement,
+    /// and overrides any compensation based on sensor altitude
+    pub fn set_ambient_pressure(&self, pascals: u32) -> Result<(), Error> {
+        self.cmd_with_word(
+            Scd41Command::SetAmbientPressure,
+            ambient_pressure_word(pascals),
+        )
+    }
+
+    /// set sensor altitude in meters above sea level for pressure compensation, sensor must be idle
+    ///
+    /// ignored once ambient pressure is set, see `set_ambient_pressure`
+    pub fn set_sensor_altitude(&self, meters: u16) -> Result<(), Error> {
+        self.cmd_with_word(Scd41Command::SetSensorAltitude, meters)
+    }
+
+    /// get 48 bit serial number of sensor, sensor must be idle
+    pub fn get_serial_number(&self) -> Result<u64, Error> {
+        let data = self.cmd_read(Scd41Command::GetSerialNumber)?;
+        validate_frame(&data)?;
+        let mut serial = 0u64;
+        for word in data.chunks_exact(3) {
+            serial = serial << 16 | u16::from_be_bytes([word[0], word[1]]) as u64;
+        }
+        Ok(serial)
+    }
+
+    /// run sensor self test, takes about 10s, sensor must be idle
+    ///
+    /// return `Ok(false)` if the test ran and the sensor reports a malfunction
+    pub fn self_test(&self) -> Result<bool, Error> {
+        self.cmd(Scd41Command::PerformSelfTest)?;
+        let status = self.read_word()?;
+        if status != 0 {
+            warn!("self test malfunction: 0x{:04x}", status);
+        }
+        Ok(status == 0)
+    }
+
+    /// persist settings for sensor, sensor must be idle
     pub fn persist_settings(&self) -> Result<(), Error> {
-        self.cmd(CMD_PERSIST_SETTINGS)?;
-        self.delay.delay_millis(600);
-        Ok(())
+        self.cmd(Scd41Command::PersistSettings)
+    }
+
+    /// reset all settings including the self calibration history to factory defaults, sensor must be idle
+    pub fn factory_reset(&self) -> Result<(), Error> {
+        self.cmd(Scd41Command::PerformFactoryReset)
+    }
+
+    /// calibrate sensor with the given co2 concentration, return the frc correction in ppm
+    ///
+    /// the sensor should have been measuring in the reference environment for at least 3 minutes
+    pub fn forced_recalibration(&mut self, target_ppm: u16) -> Result<i16, Error> {
+        const FRC_FAILED: u16 = 0xffff;
+        if self.mode().is_periodic() {
+            self.stop()?;
+        }
+        self.cmd_with_word(Scd41Command::PerformForcedRecalibration, target_ppm)?;
+        let correction = self.read_word()?;
+        if correction == FRC_FAILED {
+            return Err(Error::SimpleError(
+                "forced recalibration failed".to_string(),
+            ));
+        }
+        Ok((correction as i32 - 0x8000) as i16)
     }
 
     /// start measurement
     pub fn start(&mut self) -> Result<(), Error> {
-        self.cmd(CMD_START_PERIODIC_MEASUREMENT)?;
-        self.delay.delay_millis(500);
-        self.started = true;
+        self.cmd(Scd41Command::StartPeriodicMeasurement)?;
+        self.mode.set(Mode::Periodic);
+        self.poll_state = PollState::Idle;
+        self.wait_ready_timeout_ms = PERIODIC_WAIT_READY_TIMEOUT_MS;
+        self.sample_interval_ms = PERIODIC_SAMPLE_INTERVAL_MS;
         Ok(())
     }
 
     /// start low power measurement
     pub fn start_low_power(&mut self) -> Result<(), Error> {
-        self.cmd(CMD_START_LOW_POWER_PERIODIC_MEASUREMENT)?;
-        self.delay.delay_millis(500);
-        self.started = true;
+        self.cmd(Scd41Command::StartLowPowerPeriodicMeasurement)?;
+        self.mode.set(Mode::LowPower);
+        self.poll_state = PollState::Idle;
+        self.wait_ready_timeout_ms = LOW_POWER_WAIT_READY_TIMEOUT_MS;
+        self.sample_interval_ms = LOW_POWER_SAMPLE_INTERVAL_MS;
         Ok(())
     }
 
+    /// time between two results of the current mode, 0 if not started
+    pub fn sample_interval_ms(&self) -> u32 {
+        if self.mode().is_periodic() {
+            self.sample_interval_ms
+        } else {
+            0
+        }
+    }
+
     /// stop measurement
     pub fn stop(&mut self) -> Result<(), Error> {
-        self.cmd(CMD_STOP_PERIODIC_MEASUREMENT)?;
-        self.delay.delay_millis(500);
-        self.started = false;
+        self.cmd(Scd41Command::StopPeriodicMeasurement)?;
+        self.mode.set(Mode::Idle);
+        Ok(())
+    }
+
+    /// put sensor into sleep mode, measurement is stopped first if started
+    pub fn power_down(&mut self) -> Result<(), Error> {
+        if self.mode().is_periodic() {
+            self.stop()?;
+        }
+        self.cmd(Scd41Command::PowerDown)
+    }
+
+    /// wake up sensor from sleep mode, measurement needs to be started again
+    pub fn wake_up(&mut self) -> Result<(), Error> {
+        // the sensor does not acknowledge the wake up command
+        let cmd = Scd41Command::Wakeup;
+        self.ensure_supported(cmd)?;
+        if let Err(err) = self
+            .i2c
+            .borrow_mut()
+            .write(SCD41_ADDRESS, cmd.opcode().to_be_bytes().as_ref())
+        {
+            debug!("ignore wake up nack: {:?}", err);
+        }
+        self.delay.delay_millis(cmd.delay_ms());
+        self.mode.set(Mode::Idle);
+        // the first read after wake up may fail, check the sensor responds
+        if let Err(err) = self.get_serial_number() {
+            debug!("ignore first read error after wake up: {:?}", err);
+            self.get_serial_number()?;
+        }
         Ok(())
     }
 
     pub fn parse(&self, data: &[u8]) -> Result<MeasureResult, Error> {
-        let co2_ppm = u16::from_be_bytes([data[0], data[1]]);
-        let temp = -45.0 + 175.0 * u16::from_be_bytes([data[3], data[4]]) as f32 / 65535.0;
-        let hum = 100.0 * u16::from_be_bytes([data[6], data[7]]) as f32 / 65535.0;
-        // TODO: validate result
-        Ok(MeasureResult { co2_ppm, temp, hum })
+        parse_measurement(data)
     }
 
-    pub fn cmd(&self, cmd: u16) -> Result<(), Error> {
+    /// send command and wait for its execution time
+    pub fn cmd(&self, cmd: Scd41Command) -> Result<(), Error> {
+        if cmd.has_arg() {
+            return Err(Error::SimpleError(format!(
+                "command {:?} requires an argument",
+                cmd
+            )));
+        }
+        self.ensure_supported(cmd)?;
+        self.ensure_allowed(cmd)?;
         self.i2c
             .borrow_mut()
-            .write(SCD41_ADDRESS, cmd.to_be_bytes().as_ref())
+            .write(SCD41_ADDRESS, cmd.opcode().to_be_bytes().as_ref())?;
+        self.delay.delay_millis(cmd.delay_ms());
+        Ok(())
+    }
+
+    /// send command with argument and wait for its execution time
+    pub fn cmd_with_arg(&self, cmd: Scd41Command, args: &[u8]) -> Result<(), Error> {
+        if !cmd.has_arg() {
+            return Err(Error::SimpleError(format!(
+                "command {:?} takes no argument",
+                cmd
+            )));
+        }
+        self.ensure_allowed(cmd)?;
+        let data = [cmd.opcode().to_be_bytes().as_ref(), args].concat();
+        self.i2c.borrow_mut().write(SCD41_ADDRESS, data.as_ref())?;
+        self.delay.delay_millis(cmd.delay_ms());
+        Ok(())
+    }
+
+    /// send command then read its response
+    pub fn cmd_read(&self, cmd: Scd41Command) -> Result<Vec<u8>, Error> {
+        self.cmd(cmd)?;
+        self.read(cmd.response_len())
+    }
+
+    /// send command with a single word argument followed by its crc
+    pub fn cmd_with_word(&self, cmd: Scd41Command, word: u16) -> Result<(), Error> {
+        self.cmd_with_arg(cmd, &word_arg(word))
+    }
+
+    /// read a single word and validate its crc
+    pub fn read_word(&self) -> Result<u16, Error> {
+        let data = self.read(3)?;
+        validate_frame(&data)?;
+        Ok(u16::from_be_bytes([data[0], data[1]]))
     }
 
-    pub fn cmd_with_arg(&self, cmd: u16, args: Vec<u8>) -> Result<(), Error> {
-        let data = vec![cmd.to_be_bytes().as_ref(), args.as_slice()].concat();
-        self.i2c.borrow_mut().write(SCD41_ADDRESS, data.as_ref())
+    fn ensure_supported(&self, cmd: Scd41Command) -> Result<(), Error> {
+        if cmd.scd41_only() && self.model != Scd4xModel::Scd41 {
+            return Err(Error::SimpleError(format!(
+                "command {:?} not supported by {:?}",
+                cmd, self.model
+            )));
+        }
+        Ok(())
+    }
+
+    /// most commands are only available in idle mode, see `Mode::allows`
+    fn ensure_allowed(&self, cmd: Scd41Command) -> Result<(), Error> {
+        let mode = self.mode();
+        if !mode.allows(cmd) {
+            return Err(Error::SimpleError(format!(
+                "command {:?} not allowed in {:?} mode",
+                cmd, mode
+            )));
+        }
+        Ok(())
+    }
+
+    /// whether a new measurement can be read, checked with `is_data_ready`
+    pub fn data_ready(&self) -> Result<bool, Error> {
+        self.cmd(Scd41Command::GetDataReadyStatus)?;
+        let status = self.read_word()?;
+        debug!("data ready status: 0x{:04x}", status);
+        Ok(is_data_ready(status))
     }
 
     pub fn wait_ready(&self) -> Result<(), Error> {
-        // const READ_MASK: u16 = 0x7ff;
-        const READ_MASK: u16 = 0x8000;
+        self.wait_ready_timeout(DEFAULT_WAIT_READY_TIMEOUT_MS)
+    }
+
+    /// wait until data is ready, return `Error::Timeout` if it takes longer than `max_ms`
+    pub fn wait_ready_timeout(&self, max_ms: u32) -> Result<(), Error> {
+        const POLL_INTERVAL_MS: u32 = 1000;
+        let mut waited = 0;
         loop {
-            self.cmd(CMD_GET_DATA_READY_STATUS)?;
-            self.delay.delay_millis(1);
-            let data = self.read(3)?;
-            let flag = u16::from_be_bytes([data[0], data[1]]);
-            // if flag & READ_MASK == READ_MASK {
-            if flag != READ_MASK {
-                debug!("flag: 0x{:04x}", flag);
+            if self.data_ready()? {
                 return Ok(());
             }
-            debug!("flag: 0x{:04x}", flag);
             debug!("sensor data not ready");
-            self.delay.delay_millis(1000);
+            if waited >= max_ms {
+                warn!("wait sensor data ready timeout after {}ms", waited);
+                return Err(Error::Timeout);
+            }
+            self.delay.delay_millis(POLL_INTERVAL_MS);
+            waited += POLL_INTERVAL_MS;
         }
     }
 
@@ -173,6 +871,108 @@ impl<'a> SCD41<'a> {
     }
 }
 
+#[cfg(any(feature = "esp32", test))]
+fn parse_measurement(data: &[u8]) -> Result<MeasureResult, Error> {
+    validate_frame(data)?;
+    if data.len() != 9 {
+        return Err(Error::SimpleError(format!(
+            "measurement len {} not eq 9",
+            data.len()
+        )));
+    }
+    let co2_ppm = u16::from_be_bytes([data[0], data[1]]);
+    let temp = -45.0 + 175.0 * u16::from_be_bytes([data[3], data[4]]) as f32 / 65535.0;
+    let hum = 100.0 * u16::from_be_bytes([data[6], data[7]]) as f32 / 65535.0;
+    // NOTE: result is not validated here, use `MeasureResult::is_plausible` or `measure_validated`
+    Ok(MeasureResult { co2_ppm, temp, hum })
+}
+
+// reject results out of sensor range
+#[cfg(feature = "esp32")]
+fn validate(m: MeasureResult) -> Result<MeasureResult, Error> {
+    if !m.is_plausible() {
+        return Err(Error::SimpleError(format!(
+            "implausible measure result, co2: {}, temp: {}, hum: {}",
+            m.co2_ppm, m.temp, m.hum
+        )));
+    }
+    Ok(m)
+}
+
+/// argument of a command, the word followed by its crc
+#[cfg(any(feature = "esp32", test))]
+fn word_arg(word: u16) -> [u8; 3] {
+    let [high, low] = word.to_be_bytes();
+    [high, low, crc(&[high, low])]
+}
+
+// the sensor takes the pressure in hPa
+#[cfg(any(feature = "esp32", test))]
+fn ambient_pressure_word(pascals: u32) -> u16 {
+    (pascals / 100) as u16
+}
+
+// offset in °C is `175 * word / 65535`
+#[cfg(any(feature = "esp32", test))]
+fn temperature_offset_from_word(word: u16) -> f32 {
+    175.0 * word as f32 / 65535.0
+}
+
+#[cfg(any(feature = "esp32", test))]
+fn temperature_offset_word(offset: f32) -> u16 {
+    (offset * 65535.0 / 175.0) as u16
+}
+
+/// data ready status word, the data is ready if any of the low 11 bits is set.
+/// the other bits are undefined, e.g. `0x8000` is not ready and `0x8006` is ready
+pub fn is_data_ready(status: u16) -> bool {
+    const READY_MASK: u16 = 0x07ff;
+    status & READY_MASK != 0
+}
+
+/// check a response of the sensor, every word is followed by its crc byte
+///
+/// return `Error::FrameCrcMismatch` with the index of the first bad word
+pub fn validate_frame(data: &[u8]) -> Result<(), Error> {
+    let groups = data.chunks_exact(3);
+    if !groups.remainder().is_empty() {
+        return Err(Error::SimpleError(format!(
+            "frame len {} is not a multiple of 3",
+            data.len()
+        )));
+    }
+    for (word, group) in groups.enumerate() {
+        let expected = crc(&group[0..2]);
+        if expected != group[2] {
+            return Err(Error::FrameCrcMismatch {
+                word,
+                expected,
+                got: group[2],
+            });
+        }
+    }
+    Ok(())
+}
+
+/// probe the 7-bit addresses 0x08..=0x77 with an empty write, return the ones that ack
+///
+/// scd4x answers at 0x62, nothing at all points to wiring or power of the bus
+#[cfg(feature = "esp32")]
+pub fn scan_bus(i2c: &mut I2c<'_, Blocking>) -> Vec<u8> {
+    let found: Vec<u8> = (0x08..=0x77)
+        .filter(|&address| i2c.write(address, &[]).is_ok())
+        .collect();
+    debug!("i2c devices: {:02x?}", found);
+    found
+}
+
+/// sensirion crc-8 of a word, polynomial 0x31 and init 0xff without final xor
+///
+/// ```
+/// use co2_monitor::scd41::crc;
+/// // datasheet example
+/// assert_eq!(crc(&[0xbe, 0xef]), 0x92);
+/// ```
 pub fn crc(data: &[u8]) -> u8 {
     const CRC8_POLYNOMIAL: u8 = 0x31;
     const CRC8_INIT: u8 = 0xFF;
@@ -193,3 +993,217 @@ pub fn crc(data: &[u8]) -> u8 {
 
     crc
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // read measurement example of the datasheet, 500ppm, 25.0°C and 37.0%
+    const MEASUREMENT: [u8; 9] = [0x01, 0xf4, 0x33, 0x66, 0x67, 0xa2, 0x5e, 0xb9, 0x3c];
+
+    #[test]
+    fn parse_valid_frame() {
+        let m = parse_measurement(&MEASUREMENT).unwrap();
+        assert_eq!(m.co2_ppm, 500);
+        assert!((m.temp - 25.0).abs() < 0.01, "temp {}", m.temp);
+        assert!((m.hum - 37.0).abs() < 0.01, "hum {}", m.hum);
+    }
+
+    #[test]
+    fn ambient_pressure_encoding() {
+        // datasheet example, 98700Pa is written as 0xe000 0x03db 0x42
+        assert_eq!(Scd41Command::SetAmbientPressure.opcode(), 0xe000);
+        assert_eq!(word_arg(ambient_pressure_word(98_700)), [0x03, 0xdb, 0x42]);
+    }
+
+    #[test]
+    fn parse_rejects_flipped_crc() {
+        let mut frame = MEASUREMENT;
+        frame[5] ^= 0x01;
+        let err = parse_measurement(&frame).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::FrameCrcMismatch {
+                    word: 1,
+                    expected: 0xa2,
+                    got: 0xa3
+                }
+            ),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn crc_vectors() {
+        // datasheet example
+        assert_eq!(crc(&[0xbe, 0xef]), 0x92);
+        // words of the datasheet command examples
+        let vectors = [
+            // read_measurement, 500ppm, 25.0°C and 37.0%
+            (0x01f4, 0x33),
+            (0x6667, 0xa2),
+            (0x5eb9, 0x3c),
+            // set_ambient_pressure, 98700Pa
+            (0x03db, 0x42),
+            // get_data_ready_status
+            (0x8006, 0x04),
+            // get_temperature_offset, 6.2°C
+            (0x0912, 0x63),
+            // set_temperature_offset, 5.4°C
+            (0x07e6, 0x48),
+            // set_sensor_altitude, 1000m
+            (0x03e8, 0xd4),
+            // perform_self_test, no malfunction
+            (0x0000, 0x81),
+        ];
+        for (word, expected) in vectors {
+            let bytes = u16::to_be_bytes(word);
+            assert_eq!(crc(&bytes), expected, "word 0x{:04x}", word);
+        }
+        assert_eq!(crc(&[0xff, 0xff]), 0xac);
+        // init value for no data
+        assert_eq!(crc(&[]), 0xff);
+    }
+
+    #[test]
+    fn validate_frame_reports_first_bad_word() {
+        validate_frame(&MEASUREMENT).unwrap();
+        validate_frame(&[]).unwrap();
+
+        // bad crc of the middle word
+        let mut frame = MEASUREMENT;
+        frame[5] = 0x00;
+        let err = validate_frame(&frame).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::FrameCrcMismatch {
+                    word: 1,
+                    expected: 0xa2,
+                    got: 0x00
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        // a data byte of the middle word, the last word is bad too
+        let mut frame = MEASUREMENT;
+        frame[3] ^= 0x80;
+        frame[8] ^= 0x01;
+        let err = validate_frame(&frame).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::FrameCrcMismatch {
+                    word: 1,
+                    got: 0xa2,
+                    ..
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        // serial number of 3 words, bad middle crc
+        let serial = [0xbe, 0xef, 0x92, 0x03, 0xdb, 0x43, 0x00, 0x00, 0x81];
+        let err = validate_frame(&serial).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::FrameCrcMismatch {
+                    word: 1,
+                    expected: 0x42,
+                    got: 0x43
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        let err = validate_frame(&MEASUREMENT[..8]).unwrap_err();
+        assert!(matches!(err, Error::SimpleError(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn temperature_offset_encoding() {
+        // datasheet example of get_temperature_offset, 0x0912 0x63 is 6.2°C
+        let frame = [0x09, 0x12, 0x63];
+        validate_frame(&frame).unwrap();
+        let offset = temperature_offset_from_word(u16::from_be_bytes([frame[0], frame[1]]));
+        assert!((offset - 6.2).abs() < 0.01, "offset {}", offset);
+        let err = validate_frame(&[0x09, 0x12, 0x64]).unwrap_err();
+        assert!(
+            matches!(err, Error::FrameCrcMismatch { word: 0, .. }),
+            "{:?}",
+            err
+        );
+
+        // datasheet example of set_temperature_offset, 5.4°C is written as 0x07e6 0x48
+        assert_eq!(word_arg(temperature_offset_word(5.4)), [0x07, 0xe6, 0x48]);
+    }
+
+    #[test]
+    fn data_ready_status_word() {
+        // datasheet examples, only the low 11 bits count
+        assert!(!is_data_ready(0x8000));
+        assert!(is_data_ready(0x8006));
+        assert!(!is_data_ready(0x0000));
+        assert!(!is_data_ready(0xf800));
+        assert!(is_data_ready(0x0001));
+        assert!(is_data_ready(0x0400));
+        // 0x8006 is read as 0x80 0x06 0x04
+        validate_frame(&[0x80, 0x06, 0x04]).unwrap();
+    }
+
+    #[test]
+    fn wire_format_round_trip() {
+        let m = MeasureResult {
+            co2_ppm: 812,
+            temp: 23.4,
+            hum: 41.2,
+        };
+        let data = m.to_bytes();
+        assert_eq!(data.len(), MeasureResult::WIRE_LEN);
+        // big endian temp | hum | co2, as the metric server unpacks `>ffH`
+        assert_eq!(&data[0..4], 23.4f32.to_be_bytes().as_slice());
+        assert_eq!(&data[4..8], 41.2f32.to_be_bytes().as_slice());
+        assert_eq!(&data[8..10], [0x03, 0x2c].as_slice());
+        assert_eq!(MeasureResult::from_bytes(&data).unwrap(), m);
+
+        // the age of a queued result is appended
+        let mut aged = data.to_vec();
+        aged.extend_from_slice(&1500u32.to_be_bytes());
+        assert_eq!(MeasureResult::from_bytes(&aged).unwrap(), m);
+    }
+
+    #[test]
+    fn wire_format_rejects_short_buffer() {
+        let data = MeasureResult::default().to_bytes();
+        for len in [0, 1, MeasureResult::WIRE_LEN - 1] {
+            let err = MeasureResult::from_bytes(&data[..len]).unwrap_err();
+            assert!(
+                matches!(err, Error::SimpleError(_)),
+                "len {}: {:?}",
+                len,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn temp_in_fahrenheit() {
+        let m = |temp: f32| MeasureResult {
+            temp,
+            ..Default::default()
+        };
+        assert_eq!(m(0.0).temp_fahrenheit(), 32.0);
+        assert_eq!(m(100.0).temp_fahrenheit(), 212.0);
+        assert_eq!(m(-40.0).temp_fahrenheit(), -40.0);
+        assert_eq!(m(100.0).temp_in(TempUnit::Celsius), 100.0);
+        assert_eq!(m(100.0).temp_in(TempUnit::Fahrenheit), 212.0);
+        assert_eq!(TempUnit::Fahrenheit.suffix(), "F");
+    }
+}
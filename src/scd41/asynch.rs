@@ -0,0 +1,144 @@
+use super::{
+    is_data_ready, parse_measurement, validate_frame, MeasureResult, Scd41Command,
+    DEFAULT_WAIT_READY_TIMEOUT_MS, LOW_POWER_WAIT_READY_TIMEOUT_MS, PERIODIC_WAIT_READY_TIMEOUT_MS,
+    SCD41_ADDRESS,
+};
+use crate::error::Error;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c;
+use log::{debug, warn};
+
+/// async version of `SCD41`, the executor can run other tasks while the sensor is busy
+pub struct Scd41Async<I: I2c> {
+    i2c: I,
+    started: bool,
+    wait_ready_timeout_ms: u32,
+}
+
+impl<I: I2c> Scd41Async<I>
+where
+    Error: From<I::Error>,
+{
+    pub fn new(i2c: I) -> Self {
+        Scd41Async {
+            i2c,
+            started: false,
+            wait_ready_timeout_ms: DEFAULT_WAIT_READY_TIMEOUT_MS,
+        }
+    }
+
+    /// get measurement results from sensor, return `Error::NotStarted` if not started
+    pub async fn measure(&mut self) -> Result<MeasureResult, Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+        self.wait_ready_timeout(self.wait_ready_timeout_ms).await?;
+        let data = self.cmd_read(Scd41Command::ReadMeasurement).await?;
+        parse_measurement(data.as_slice())
+    }
+
+    /// get measurement results from sensor, reject results out of sensor range
+    pub async fn measure_validated(&mut self) -> Result<MeasureResult, Error> {
+        let m = self.measure().await?;
+        if !m.is_plausible() {
+            return Err(Error::SimpleError(format!(
+                "implausible measure result, co2: {}, temp: {}, hum: {}",
+                m.co2_ppm, m.temp, m.hum
+            )));
+        }
+        Ok(m)
+    }
+
+    /// get measurement results from sensor, the 5s measuring time is awaited
+    pub async fn measure_oneshot(&mut self) -> Result<MeasureResult, Error> {
+        self.cmd(Scd41Command::OneshotMeasurement).await?;
+        // the result is not returned by the command, read it with read_measurement
+        let data = self.cmd_read(Scd41Command::ReadMeasurement).await?;
+        parse_measurement(data.as_slice())
+    }
+
+    /// start measurement
+    pub async fn start(&mut self) -> Result<(), Error> {
+        self.cmd(Scd41Command::StartPeriodicMeasurement).await?;
+        self.started = true;
+        self.wait_ready_timeout_ms = PERIODIC_WAIT_READY_TIMEOUT_MS;
+        Ok(())
+    }
+
+    /// start low power measurement
+    pub async fn start_low_power(&mut self) -> Result<(), Error> {
+        self.cmd(Scd41Command::StartLowPowerPeriodicMeasurement)
+            .await?;
+        self.started = true;
+        self.wait_ready_timeout_ms = LOW_POWER_WAIT_READY_TIMEOUT_MS;
+        Ok(())
+    }
+
+    /// stop measurement
+    pub async fn stop(&mut self) -> Result<(), Error> {
+        self.cmd(Scd41Command::StopPeriodicMeasurement).await?;
+        self.started = false;
+        Ok(())
+    }
+
+    /// whether a new measurement can be read
+    pub async fn data_ready(&mut self) -> Result<bool, Error> {
+        let data = self.cmd_read(Scd41Command::GetDataReadyStatus).await?;
+        validate_frame(&data)?;
+        let status = u16::from_be_bytes([data[0], data[1]]);
+        debug!("data ready status: 0x{:04x}", status);
+        Ok(is_data_ready(status))
+    }
+
+    pub async fn wait_ready(&mut self) -> Result<(), Error> {
+        self.wait_ready_timeout(DEFAULT_WAIT_READY_TIMEOUT_MS).await
+    }
+
+    /// wait until data is ready, return `Error::Timeout` if it takes longer than `max_ms`
+    pub async fn wait_ready_timeout(&mut self, max_ms: u32) -> Result<(), Error> {
+        const POLL_INTERVAL_MS: u32 = 1000;
+        let mut waited = 0;
+        loop {
+            if self.data_ready().await? {
+                return Ok(());
+            }
+            debug!("sensor data not ready");
+            if waited >= max_ms {
+                warn!("wait sensor data ready timeout after {}ms", waited);
+                return Err(Error::Timeout);
+            }
+            Timer::after_millis(POLL_INTERVAL_MS as u64).await;
+            waited += POLL_INTERVAL_MS;
+        }
+    }
+
+    /// send command and wait for its execution time
+    pub async fn cmd(&mut self, cmd: Scd41Command) -> Result<(), Error> {
+        if cmd.has_arg() {
+            return Err(Error::SimpleError(format!(
+                "command {:?} requires an argument",
+                cmd
+            )));
+        }
+        self.i2c
+            .write(SCD41_ADDRESS, cmd.opcode().to_be_bytes().as_ref())
+            .await?;
+        Timer::after_millis(cmd.delay_ms() as u64).await;
+        Ok(())
+    }
+
+    /// send command then read its response
+    pub async fn cmd_read(&mut self, cmd: Scd41Command) -> Result<Vec<u8>, Error> {
+        self.cmd(cmd).await?;
+        self.read(cmd.response_len()).await
+    }
+
+    pub async fn read(&mut self, size: usize) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0u8; size];
+        self.i2c.read(SCD41_ADDRESS, buf.as_mut_slice()).await?;
+        Ok(buf)
+    }
+}
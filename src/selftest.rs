@@ -0,0 +1,76 @@
+use crate::canvas::Canvas;
+use crate::e_paper::Rotation;
+use crate::error::Error;
+use alloc::vec::Vec;
+use embedded_graphics::geometry::OriginDimensions;
+use embedded_graphics::pixelcolor::{Gray4, GrayColor};
+use embedded_graphics::prelude::{Point, Size};
+use log::{info, warn};
+
+// line height of `FONT_10X20`, 5 lines fit on a 128 px high panel
+const LINE_HEIGHT: i32 = 24;
+// width of "PASS" and "FAIL" in `FONT_10X20` plus margin
+const RESULT_WIDTH: i32 = 50;
+
+/// boot checklist shown on the panel, so the device can be diagnosed without a laptop
+///
+/// each step is recorded as passed or failed, a failed step does not stop the next ones
+#[derive(Debug, Default)]
+pub struct SelfTest {
+    results: Vec<(&'static str, bool)>,
+}
+
+impl SelfTest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// record the result of step `name`
+    pub fn record(&mut self, name: &'static str, passed: bool) {
+        if passed {
+            info!("self test {}: pass", name);
+        } else {
+            warn!("self test {}: fail", name);
+        }
+        self.results.push((name, passed));
+    }
+
+    /// record step `name` by `result`, return the value if passed
+    pub fn check<T>(&mut self, name: &'static str, result: Result<T, Error>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.record(name, true);
+                Some(value)
+            }
+            Err(err) => {
+                warn!("self test {} error: {:?}", name, err);
+                self.record(name, false);
+                None
+            }
+        }
+    }
+
+    pub fn results(&self) -> &[(&'static str, bool)] {
+        &self.results
+    }
+
+    /// whether all steps passed
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|(_, passed)| *passed)
+    }
+
+    /// render a black white frame with one step per line, the name on the left
+    /// and PASS or FAIL on the right
+    pub fn render(&self, size: &Size, rotation: Rotation) -> Result<Vec<u8>, Error> {
+        let mut canvas = Canvas::with_rotation(size, rotation).with_fixed_palette();
+        canvas.clear(Gray4::WHITE);
+        let result_x = canvas.size().width as i32 - RESULT_WIDTH;
+        for (idx, (name, passed)) in self.results.iter().enumerate() {
+            let y = LINE_HEIGHT * (idx as i32 + 1);
+            canvas.draw_text(name, Point::new(10, y))?;
+            let result = if *passed { "PASS" } else { "FAIL" };
+            canvas.draw_text(result, Point::new(result_x, y))?;
+        }
+        Ok(canvas.render_black_white())
+    }
+}
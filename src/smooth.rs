@@ -0,0 +1,99 @@
+use crate::scd41::MeasureResult;
+
+/// exponential moving average of each field of the measure results
+///
+/// `avg = avg + alpha * (value - avg)`, the first value is taken as is.
+/// a step is covered by `1 - (1 - alpha)^n` after n updates, e.g. 0.3 covers 66% in 3 updates
+#[derive(Debug, Clone, Copy)]
+pub struct Smoother {
+    alpha: f32,
+    // co2 is kept as float so small steps are not rounded away
+    avg: Option<(f32, f32, f32)>,
+}
+
+impl Smoother {
+    /// `alpha` in (0, 1], 1 disables smoothing, smaller is smoother but slower
+    pub fn new(alpha: f32) -> Self {
+        Smoother {
+            alpha: alpha.clamp(f32::EPSILON, 1.0),
+            avg: None,
+        }
+    }
+
+    /// add `m` and return the smoothed result
+    pub fn update(&mut self, m: &MeasureResult) -> MeasureResult {
+        let value = (m.co2_ppm as f32, m.temp, m.hum);
+        let (co2, temp, hum) = match self.avg {
+            None => value,
+            Some((co2, temp, hum)) => (
+                co2 + self.alpha * (value.0 - co2),
+                temp + self.alpha * (value.1 - temp),
+                hum + self.alpha * (value.2 - hum),
+            ),
+        };
+        self.avg = Some((co2, temp, hum));
+        MeasureResult {
+            co2_ppm: (co2 + 0.5) as u16,
+            temp,
+            hum,
+        }
+    }
+
+    /// forget the average, the next value is taken as is
+    pub fn reset(&mut self) {
+        self.avg = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(co2_ppm: u16, temp: f32, hum: f32) -> MeasureResult {
+        MeasureResult { co2_ppm, temp, hum }
+    }
+
+    #[test]
+    fn step_converges_at_alpha_rate() {
+        let mut smoother = Smoother::new(0.5);
+        // the first value is taken as is
+        assert_eq!(
+            smoother.update(&reading(400, 20.0, 40.0)),
+            reading(400, 20.0, 40.0)
+        );
+        // half of the remaining step is covered by each update
+        let expected = [(600, 22.0, 45.0), (700, 23.0, 47.5), (750, 23.5, 48.75)];
+        for (co2_ppm, temp, hum) in expected {
+            let m = smoother.update(&reading(800, 24.0, 50.0));
+            assert_eq!(m, reading(co2_ppm, temp, hum));
+        }
+
+        smoother.reset();
+        assert_eq!(
+            smoother.update(&reading(800, 24.0, 50.0)),
+            reading(800, 24.0, 50.0)
+        );
+    }
+
+    #[test]
+    fn step_coverage_after_n_updates() {
+        // 0.3 covers 1 - 0.7^3 = 65.7% of a step in 3 updates
+        let mut smoother = Smoother::new(0.3);
+        smoother.update(&reading(0, 0.0, 0.0));
+        let mut m = MeasureResult::default();
+        for _ in 0..3 {
+            m = smoother.update(&reading(1000, 10.0, 100.0));
+        }
+        assert_eq!(m.co2_ppm, 657);
+        assert!((m.temp - 6.57).abs() < 1e-4, "temp {}", m.temp);
+        assert!((m.hum - 65.7).abs() < 1e-3, "hum {}", m.hum);
+
+        // alpha of 1 disables smoothing
+        let mut smoother = Smoother::new(1.0);
+        smoother.update(&reading(400, 20.0, 40.0));
+        assert_eq!(
+            smoother.update(&reading(800, 24.0, 50.0)),
+            reading(800, 24.0, 50.0)
+        );
+    }
+}
@@ -1,4 +1,6 @@
 use alloc::string::String;
+#[cfg(feature = "esp32")]
+use core::sync::atomic::{AtomicUsize, Ordering};
 use log::debug;
 
 
@@ -19,7 +21,69 @@ impl Drop for DebugPrinter {
     }
 }
 
+/// peak heap usage, updated by `debug_alloc`
+#[cfg(feature = "esp32")]
+pub static HEAP_WATERMARK: HeapWatermark = HeapWatermark::new();
+
+#[cfg(feature = "esp32")]
 pub fn debug_alloc(s: &str) {
+    HEAP_WATERMARK.poll();
     let stats = esp_alloc::HEAP.stats();
-    debug!("{} heap stats: {}", s, stats);
+    debug!(
+        "{} heap stats: {}, peak used: {}",
+        s,
+        stats,
+        HEAP_WATERMARK.peak()
+    );
+}
+
+/// free heap bytes
+///
+/// NOTE: the free bytes may be fragmented, a single allocation of this size can still fail,
+/// so keep a margin when checking a large buffer against it
+#[cfg(feature = "esp32")]
+pub fn available_bytes() -> usize {
+    esp_alloc::HEAP.free()
+}
+
+/// the host has no heap limit
+#[cfg(not(feature = "esp32"))]
+pub fn available_bytes() -> usize {
+    usize::MAX
+}
+
+/// max used heap bytes seen when polled
+#[cfg(feature = "esp32")]
+pub struct HeapWatermark {
+    peak: AtomicUsize,
+}
+
+#[cfg(feature = "esp32")]
+impl HeapWatermark {
+    pub const fn new() -> Self {
+        HeapWatermark {
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    /// record current usage, return the peak
+    pub fn poll(&self) -> usize {
+        let used = esp_alloc::HEAP.used();
+        self.peak.fetch_max(used, Ordering::Relaxed).max(used)
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.peak.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "esp32")]
+impl Default for HeapWatermark {
+    fn default() -> Self {
+        Self::new()
+    }
 }